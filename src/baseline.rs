@@ -0,0 +1,266 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// Known and accepted discrepancies hidden from the compare result
+#[derive(Default, Serialize, Deserialize)]
+pub struct Baseline {
+    #[serde(default, rename = "entry")]
+    pub entries: Vec<BaselineEntry>,
+}
+
+/// Accepted discrepancy of a condition, identified by condition ID or by one of its Meldung IDs
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cond_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meldung_id: Option<String>,
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+}
+
+impl BaselineEntry {
+    /// Checks if the entry has not expired at the given date. The expiry date itself is included.
+    pub fn is_active(&self, today: NaiveDate) -> bool {
+        match self.expires.as_deref().and_then(parse_date) {
+            Some(expires) => expires >= today,
+            None => true,
+        }
+    }
+}
+
+impl Baseline {
+    /// Loads the given baseline file
+    pub fn load(path: &Path) -> Result<Baseline, String> {
+        let content = fs::read_to_string(path).map_err(|_| {
+            format!(
+                "Kann Baseline-Datei '{}' nicht lesen",
+                path.to_str().unwrap_or_default()
+            )
+        })?;
+
+        Self::parse(&content).map_err(|err| {
+            format!(
+                "Ungültige Baseline-Datei '{}': {}",
+                path.to_str().unwrap_or_default(),
+                err
+            )
+        })
+    }
+
+    /// Loads the given baseline file or an empty baseline if the file does not exist
+    pub fn load_or_default(path: &Path) -> Result<Baseline, String> {
+        match path.exists() {
+            true => Self::load(path),
+            false => Ok(Baseline::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = toml::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(path, content).map_err(|err| {
+            format!(
+                "Kann Baseline-Datei '{}' nicht schreiben: {}",
+                path.to_str().unwrap_or_default(),
+                err
+            )
+        })
+    }
+
+    fn parse(content: &str) -> Result<Baseline, String> {
+        let baseline =
+            toml::from_str::<Baseline>(content).map_err(|err| err.message().to_string())?;
+
+        for entry in &baseline.entries {
+            if entry.cond_id.is_some() == entry.meldung_id.is_some() {
+                return Err(
+                    "Jeder Eintrag benötigt entweder 'cond_id' oder 'meldung_id'".to_string(),
+                );
+            }
+            if let Some(ref expires) = entry.expires {
+                if parse_date(expires).is_none() {
+                    return Err(format!(
+                        "Ungültiges Ablaufdatum '{}', bitte im Format 'yyyy-mm-dd' angeben",
+                        expires
+                    ));
+                }
+            }
+        }
+
+        Ok(baseline)
+    }
+
+    /// Condition IDs of all entries active at the given date. Meldung IDs are resolved using
+    /// the given condition IDs by Meldung ID, unknown Meldung IDs are ignored.
+    pub fn condition_ids(
+        &self,
+        today: NaiveDate,
+        meldungen: &HashMap<String, String>,
+    ) -> HashSet<String> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.is_active(today))
+            .filter_map(|entry| match (&entry.cond_id, &entry.meldung_id) {
+                (Some(cond_id), _) => Some(cond_id.to_string()),
+                (_, Some(meldung_id)) => meldungen.get(meldung_id).cloned(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Adds an entry for the condition unless there is an active one already. Expired entries of
+    /// the condition are renewed with the given reason and expiry date.
+    pub fn add(
+        &mut self,
+        cond_id: &str,
+        reason: &str,
+        expires: Option<&str>,
+        today: NaiveDate,
+    ) -> BaselineChange {
+        let entries = self
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.cond_id.as_deref() == Some(cond_id))
+            .collect::<Vec<_>>();
+
+        if entries.iter().any(|entry| entry.is_active(today)) {
+            return BaselineChange::Existing;
+        }
+
+        if let Some(entry) = entries.into_iter().next() {
+            entry.reason = reason.to_string();
+            entry.expires = expires.map(|expires| expires.to_string());
+            return BaselineChange::Renewed;
+        }
+
+        self.entries.push(BaselineEntry {
+            cond_id: Some(cond_id.to_string()),
+            meldung_id: None,
+            reason: reason.to_string(),
+            expires: expires.map(|expires| expires.to_string()),
+        });
+        BaselineChange::Added
+    }
+}
+
+/// Result of adding a condition to the baseline
+#[derive(Debug, PartialEq)]
+pub enum BaselineChange {
+    /// New entry for the condition
+    Added,
+    /// Expired entry of the condition with new reason and expiry date
+    Renewed,
+    /// Active entry of the condition already present
+    Existing,
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::NaiveDate;
+
+    use crate::baseline::{Baseline, BaselineChange};
+
+    #[test]
+    fn should_resolve_active_condition_ids() {
+        let baseline = Baseline::parse(
+            r#"
+            [[entry]]
+            cond_id = "c1"
+            reason = "Nachträgliche Korrektur"
+
+            [[entry]]
+            cond_id = "c2"
+            reason = "Abgelaufen"
+            expires = "2024-05-31"
+
+            [[entry]]
+            meldung_id = "TEST1727528"
+            reason = "Widerspruch"
+            expires = "2024-06-01"
+
+            [[entry]]
+            meldung_id = "UNKNOWN"
+            reason = "Nicht im Diagnosejahr"
+            "#,
+        )
+        .unwrap();
+        let meldungen = HashMap::from([("TEST1727528".to_string(), "c3".to_string())]);
+
+        let actual =
+            baseline.condition_ids(NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(), &meldungen);
+
+        assert_eq!(actual.len(), 2);
+        assert!(actual.contains("c1"));
+        assert!(actual.contains("c3"));
+    }
+
+    #[test]
+    fn should_reject_invalid_entries() {
+        assert!(Baseline::parse("[[entry]]\nreason = \"Keine ID\"").is_err());
+        assert!(Baseline::parse(
+            "[[entry]]\ncond_id = \"c1\"\nmeldung_id = \"m1\"\nreason = \"Beide IDs\""
+        )
+        .is_err());
+        assert!(Baseline::parse(
+            "[[entry]]\ncond_id = \"c1\"\nreason = \"Datum\"\nexpires = \"31.12.2024\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn should_add_new_conditions_and_renew_expired_entries() {
+        let today = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let mut baseline = Baseline::default();
+
+        assert_eq!(
+            baseline.add("c1", "Bekannt", Some("2024-12-31"), today),
+            BaselineChange::Added
+        );
+        assert_eq!(
+            baseline.add("c1", "Erneut", None, today),
+            BaselineChange::Existing
+        );
+        assert_eq!(baseline.entries.len(), 1);
+        assert_eq!(baseline.entries[0].reason, "Bekannt");
+
+        let later = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+        assert_eq!(
+            baseline.add("c1", "Erneut geprüft", Some("2025-12-31"), later),
+            BaselineChange::Renewed
+        );
+        assert_eq!(baseline.entries.len(), 1);
+        assert_eq!(baseline.entries[0].reason, "Erneut geprüft");
+        assert_eq!(baseline.entries[0].expires.as_deref(), Some("2025-12-31"));
+    }
+}
@@ -0,0 +1,408 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use itertools::Itertools;
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::Serialize;
+
+/// Element of an XML document in canonical form. Two documents with the same content are equal
+/// regardless of attribute order, escaping of entities and character references, namespace
+/// prefixes, whitespace-only text nodes, self-closing tags, comments and the XML declaration.
+#[derive(Debug, Default, PartialEq)]
+pub struct CanonicalElement {
+    /// Local name without namespace prefix
+    pub name: String,
+    /// Unescaped attribute values by local name, without namespace declarations
+    pub attributes: BTreeMap<String, String>,
+    /// Unescaped and trimmed text content of this element, without text of child elements
+    pub text: String,
+    pub children: Vec<CanonicalElement>,
+}
+
+impl CanonicalElement {
+    /// Parses the root element of the given XML document or fragment
+    pub fn parse(xml: &str) -> Result<CanonicalElement, String> {
+        let mut reader = Reader::from_str(xml);
+        let mut open = Vec::<CanonicalElement>::new();
+
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(ref element)) => open.push(Self::from_start(element)?),
+                Ok(Event::Empty(ref element)) => {
+                    let element = Self::from_start(element)?;
+                    match open.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                Ok(Event::End(_)) => {
+                    let mut element = open.pop().ok_or("Unerwartetes Element-Ende")?;
+                    element.text = element.text.trim().to_string();
+                    match open.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                Ok(Event::Text(text)) => {
+                    let text = text.unescape().map_err(|err| err.to_string())?;
+                    if let Some(element) = open.last_mut() {
+                        if !text.trim().is_empty() {
+                            element.text.push_str(&text);
+                        }
+                    }
+                }
+                Ok(Event::CData(data)) => {
+                    if let Some(element) = open.last_mut() {
+                        element.text.push_str(&String::from_utf8_lossy(&data));
+                    }
+                }
+                Ok(Event::Eof) => return Err("Kein vollständiges XML-Element gefunden".into()),
+                Err(err) => return Err(format!("{} an Position {}", err, reader.error_position())),
+                Ok(_) => continue,
+            }
+        }
+    }
+
+    /// Canonical XML string in a single line
+    pub fn to_xml_string(&self) -> String {
+        let mut xml = String::new();
+        self.write_xml(&mut xml, None);
+        xml
+    }
+
+    /// Canonical XML string with one element per line indented by level
+    pub fn to_pretty_xml_string(&self) -> String {
+        let mut xml = String::new();
+        self.write_xml(&mut xml, Some(0));
+        xml
+    }
+
+    fn write_xml(&self, xml: &mut String, level: Option<usize>) {
+        let indent = "  ".repeat(level.unwrap_or_default());
+        xml.push_str(&format!("{}<{}", indent, self.name));
+        self.attributes.iter().for_each(|(name, value)| {
+            xml.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+        });
+
+        if self.text.is_empty() && self.children.is_empty() {
+            xml.push_str("/>");
+        } else {
+            xml.push('>');
+            xml.push_str(&escape(&self.text));
+            if !self.children.is_empty() {
+                self.children.iter().for_each(|child| {
+                    if level.is_some() {
+                        xml.push('\n');
+                    }
+                    child.write_xml(xml, level.map(|level| level + 1));
+                });
+                if level.is_some() {
+                    xml.push('\n');
+                    xml.push_str(&indent);
+                }
+            }
+            xml.push_str(&format!("</{}>", self.name));
+        }
+
+        if level == Some(0) {
+            xml.push('\n');
+        }
+    }
+
+    fn children_named(&self, name: &str) -> Vec<&CanonicalElement> {
+        self.children
+            .iter()
+            .filter(|child| child.name == name)
+            .collect_vec()
+    }
+
+    fn from_start(element: &BytesStart) -> Result<CanonicalElement, String> {
+        let mut attributes = BTreeMap::new();
+        for attribute in element.attributes() {
+            let attribute = attribute.map_err(|err| err.to_string())?;
+            if attribute.key.as_namespace_binding().is_some() {
+                continue;
+            }
+            attributes.insert(
+                String::from_utf8_lossy(attribute.key.local_name().as_ref()).to_string(),
+                attribute
+                    .unescape_value()
+                    .map_err(|err| err.to_string())?
+                    .to_string(),
+            );
+        }
+
+        Ok(CanonicalElement {
+            name: String::from_utf8_lossy(element.local_name().as_ref()).to_string(),
+            attributes,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Changed,
+    Added,
+    Removed,
+}
+
+impl Display for ChangeKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ChangeKind::Changed => "geändert",
+                ChangeKind::Added => "hinzugefügt",
+                ChangeKind::Removed => "entfernt",
+            }
+        )
+    }
+}
+
+/// Changed, added or removed element, attribute or text identified by its XPath.
+/// Values of elements are given as canonical XML string.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct XmlChange {
+    pub path: String,
+    pub kind: ChangeKind,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl XmlChange {
+    fn new(path: String, old: Option<String>, new: Option<String>) -> Self {
+        let kind = match (&old, &new) {
+            (None, _) => ChangeKind::Added,
+            (_, None) => ChangeKind::Removed,
+            _ => ChangeKind::Changed,
+        };
+        XmlChange {
+            path,
+            kind,
+            old,
+            new,
+        }
+    }
+}
+
+/// Changes from the old to the new element in document order. Child elements are matched by
+/// name and position among siblings of the same name, the position is part of the XPath if
+/// there is more than one sibling with that name.
+pub fn diff(old: &CanonicalElement, new: &CanonicalElement) -> Vec<XmlChange> {
+    let mut changes = vec![];
+    if old.name == new.name {
+        diff_element(&format!("/{}", old.name), old, new, &mut changes);
+    } else {
+        changes.push(XmlChange::new(
+            format!("/{}", old.name),
+            Some(old.to_xml_string()),
+            None,
+        ));
+        changes.push(XmlChange::new(
+            format!("/{}", new.name),
+            None,
+            Some(new.to_xml_string()),
+        ));
+    }
+    changes
+}
+
+fn diff_element(
+    path: &str,
+    old: &CanonicalElement,
+    new: &CanonicalElement,
+    changes: &mut Vec<XmlChange>,
+) {
+    old.attributes
+        .keys()
+        .chain(new.attributes.keys())
+        .unique()
+        .sorted()
+        .for_each(|name| {
+            let old_value = old.attributes.get(name);
+            let new_value = new.attributes.get(name);
+            if old_value != new_value {
+                changes.push(XmlChange::new(
+                    format!("{}/@{}", path, name),
+                    old_value.cloned(),
+                    new_value.cloned(),
+                ));
+            }
+        });
+
+    if old.text != new.text {
+        let value = |text: &str| (!text.is_empty()).then(|| text.to_string());
+        changes.push(XmlChange::new(
+            format!("{}/text()", path),
+            value(&old.text),
+            value(&new.text),
+        ));
+    }
+
+    old.children
+        .iter()
+        .chain(new.children.iter())
+        .map(|child| child.name.as_str())
+        .unique()
+        .for_each(|name| {
+            let old_children = old.children_named(name);
+            let new_children = new.children_named(name);
+            let count = old_children.len().max(new_children.len());
+
+            for idx in 0..count {
+                let child_path = match count {
+                    1 => format!("{}/{}", path, name),
+                    _ => format!("{}/{}[{}]", path, name, idx + 1),
+                };
+                match (old_children.get(idx), new_children.get(idx)) {
+                    (Some(old_child), Some(new_child)) => {
+                        diff_element(&child_path, old_child, new_child, changes)
+                    }
+                    (old_child, new_child) => changes.push(XmlChange::new(
+                        child_path,
+                        old_child.map(|child| child.to_xml_string()),
+                        new_child.map(|child| child.to_xml_string()),
+                    )),
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::canonical::{diff, CanonicalElement, ChangeKind, XmlChange};
+
+    #[test]
+    fn should_parse_equal_canonical_elements() {
+        let expected = CanonicalElement::parse(
+            r#"<Meldung Meldung_ID="M1" Melder_ID="A"><Diagnose><Text>Tumor &amp; Rezidiv</Text><Leer></Leer></Diagnose></Meldung>"#,
+        )
+        .unwrap();
+
+        let actual = CanonicalElement::parse(
+            r#"<?xml version="1.0" encoding="ISO-8859-1"?>
+            <!-- Kommentar -->
+            <adt:Meldung xmlns:adt="http://www.gekid.de/namespace" Melder_ID="A" Meldung_ID="&#77;1">
+                <adt:Diagnose>
+                    <adt:Text><![CDATA[Tumor & Rezidiv]]></adt:Text>
+                    <adt:Leer/>
+                </adt:Diagnose>
+            </adt:Meldung>"#,
+        )
+        .unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn should_detect_different_content() {
+        let expected = CanonicalElement::parse(r#"<Meldung A="1"><Text>a</Text></Meldung>"#);
+
+        assert_ne!(
+            CanonicalElement::parse(r#"<Meldung A="2"><Text>a</Text></Meldung>"#),
+            expected
+        );
+        assert_ne!(
+            CanonicalElement::parse(r#"<Meldung A="1"><Text>b</Text></Meldung>"#),
+            expected
+        );
+        assert_ne!(
+            CanonicalElement::parse(r#"<Meldung A="1"><Text>a</Text><Text/></Meldung>"#),
+            expected
+        );
+    }
+
+    #[test]
+    fn should_return_error_on_malformed_xml() {
+        assert!(CanonicalElement::parse("<Meldung><Text></Meldung>").is_err());
+        assert!(CanonicalElement::parse("<Meldung>").is_err());
+        assert!(CanonicalElement::parse("").is_err());
+    }
+
+    #[test]
+    fn should_write_canonical_xml_string() {
+        let element = CanonicalElement::parse(
+            r#"<adt:Meldung xmlns:adt="urn:test" B="2" A="&lt;1&gt;"><adt:Text>a &amp; b</adt:Text><adt:Leer></adt:Leer></adt:Meldung>"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            element.to_xml_string(),
+            r#"<Meldung A="&lt;1&gt;" B="2"><Text>a &amp; b</Text><Leer/></Meldung>"#
+        );
+        assert_eq!(
+            element.to_pretty_xml_string(),
+            "<Meldung A=\"&lt;1&gt;\" B=\"2\">\n  <Text>a &amp; b</Text>\n  <Leer/>\n</Meldung>\n"
+        );
+    }
+
+    #[test]
+    fn should_diff_elements_with_xpath() {
+        let old = CanonicalElement::parse(
+            r#"<Meldung Meldung_ID="M1" Melder_ID="A"><Diagnose Tumor_ID="1"><Text>C17.1</Text></Diagnose><OP><Datum>01.01.2024</Datum></OP><OP/></Meldung>"#,
+        )
+        .unwrap();
+        let new = CanonicalElement::parse(
+            r#"<Meldung Meldung_ID="M1" Meldeanlass="diagnose"><Diagnose Tumor_ID="1"><Text>C17.2</Text></Diagnose><OP><Datum>01.01.2024</Datum></OP></Meldung>"#,
+        )
+        .unwrap();
+
+        let actual = diff(&old, &new);
+
+        assert_eq!(
+            actual,
+            vec![
+                XmlChange {
+                    path: "/Meldung/@Meldeanlass".into(),
+                    kind: ChangeKind::Added,
+                    old: None,
+                    new: Some("diagnose".into()),
+                },
+                XmlChange {
+                    path: "/Meldung/@Melder_ID".into(),
+                    kind: ChangeKind::Removed,
+                    old: Some("A".into()),
+                    new: None,
+                },
+                XmlChange {
+                    path: "/Meldung/Diagnose/Text/text()".into(),
+                    kind: ChangeKind::Changed,
+                    old: Some("C17.1".into()),
+                    new: Some("C17.2".into()),
+                },
+                XmlChange {
+                    path: "/Meldung/OP[2]".into(),
+                    kind: ChangeKind::Removed,
+                    old: Some("<OP/>".into()),
+                    new: None,
+                },
+            ]
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+}
@@ -18,7 +18,7 @@
  * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
  */
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use regex::Regex;
 use std::path::PathBuf;
 
@@ -26,10 +26,178 @@ use std::path::PathBuf;
 #[command(author, version, about)]
 #[command(arg_required_else_help(true), disable_help_flag(true))]
 pub struct Cli {
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "Ausgabeformat"
+    )]
+    pub output_format: OutputFormat,
+    #[arg(
+        long,
+        global = true,
+        env = "BZKF_CONFIG",
+        help = "Konfigurationsdatei. Standard: 'bzkf-rwdp-check.toml' im Konfigurationsverzeichnis des Benutzers"
+    )]
+    pub config: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        env = "BZKF_PROFILE",
+        help = "Name des Datenbank-Profils aus der Konfigurationsdatei"
+    )]
+    pub profile: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "TOML-Datei mit abweichender Definition der ICD-10-Gruppen"
+    )]
+    pub icd_groups: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Version der ICD-10-Gruppen. Standard: Version passend zum Diagnosejahr"
+    )]
+    pub grouping_version: Option<String>,
     #[command(subcommand)]
     pub cmd: SubCommand,
 }
 
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    #[value(help = "Formatierte Textausgabe")]
+    Text,
+    #[value(help = "Maschinenlesbare Ausgabe im JSON-Format")]
+    Json,
+}
+
+#[derive(Args)]
+pub struct DatabaseArgs {
+    #[arg(
+        short = 'D',
+        long,
+        env = "BZKF_DB_NAME",
+        help = "Datenbank-Name [Standard: onkostar]"
+    )]
+    pub database: Option<String>,
+    #[arg(
+        short = 'h',
+        long,
+        env = "BZKF_DB_HOST",
+        help = "Datenbank-Host [Standard: localhost]"
+    )]
+    pub host: Option<String>,
+    #[arg(
+        short = 'P',
+        long,
+        env = "BZKF_DB_PORT",
+        help = "Datenbank-Port [Standard: 3306]"
+    )]
+    pub port: Option<u16>,
+    #[arg(
+        short = 'p',
+        long,
+        env = "BZKF_DB_PASSWORD",
+        hide_env_values = true,
+        help = "Passwort. Wenn nicht angegeben, wird danach gefragt"
+    )]
+    pub password: Option<String>,
+    #[arg(short = 'u', long, env = "BZKF_DB_USER", help = "Benutzername")]
+    pub user: Option<String>,
+    #[arg(
+        short = 'S',
+        long,
+        env = "BZKF_DB_SOCKET",
+        help = "Verbindung über Unix-Socket (bzw. Named Pipe unter Windows) statt über Host und Port"
+    )]
+    pub socket: Option<String>,
+    #[arg(long, help = "Verschlüsselte Verbindung (TLS) verwenden")]
+    pub tls: bool,
+    #[arg(
+        long,
+        help = "CA-Zertifikat (PEM oder DER) zur Prüfung des Server-Zertifikats. Aktiviert TLS"
+    )]
+    pub tls_ca_file: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Client-Zertifikat mit Schlüssel als PKCS#12-Archiv. Aktiviert TLS"
+    )]
+    pub tls_client_identity: Option<PathBuf>,
+    #[arg(
+        long,
+        env = "BZKF_DB_TLS_CLIENT_IDENTITY_PASSWORD",
+        hide_env_values = true,
+        help = "Passwort des PKCS#12-Archivs"
+    )]
+    pub tls_client_identity_password: Option<String>,
+    #[arg(
+        long,
+        help = "Hostname im Server-Zertifikat bei TLS-Verbindung nicht prüfen"
+    )]
+    pub tls_skip_verify_hostname: bool,
+}
+
+#[derive(Args)]
+pub struct FilterArgs {
+    #[arg(long, value_parser = value_is_date, help = "Ignoriere LKR-Exporte seit Datum")]
+    pub ignore_exports_since: Option<String>,
+    #[arg(long, help = "Ignoriere Meldungen, die nicht im oBDS 2.x Format sind")]
+    pub ignore_non_obds_2: bool,
+    #[arg(
+        long,
+        conflicts_with = "ignore_non_obds_2",
+        help = "Meldungen, die nicht im oBDS 2.x Format sind, nicht ignorieren (überschreibt Konfigurationsdatei)"
+    )]
+    pub no_ignore_non_obds_2: bool,
+    #[arg(long, help = "Meldungen mit externer Diagnose einschließen")]
+    pub include_extern: bool,
+    #[arg(
+        long,
+        conflicts_with = "include_extern",
+        help = "Meldungen mit externer Diagnose nicht einschließen (überschreibt Konfigurationsdatei)"
+    )]
+    pub no_include_extern: bool,
+    #[arg(
+        long,
+        help = "Meldungen mit Meldeanlass 'histologie_zytologie' einschließen"
+    )]
+    pub include_histo_zyto: bool,
+    #[arg(
+        long,
+        conflicts_with = "include_histo_zyto",
+        help = "Meldungen mit Meldeanlass 'histologie_zytologie' nicht einschließen (überschreibt Konfigurationsdatei)"
+    )]
+    pub no_include_histo_zyto: bool,
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = true)]
+pub struct DiagnosisArgs {
+    #[arg(
+        short = 'y',
+        long,
+        value_delimiter = ',',
+        conflicts_with_all = ["diagnosis_from", "diagnosis_to"],
+        help = "Jahr der Diagnose. Mehrere Jahre durch Komma getrennt ergeben je einen Abschnitt"
+    )]
+    pub year: Vec<String>,
+    #[arg(
+        long,
+        value_parser = value_is_date,
+        requires = "diagnosis_to",
+        help = "Diagnosedatum ab (einschließlich) statt Diagnosejahr"
+    )]
+    pub diagnosis_from: Option<String>,
+    #[arg(
+        long,
+        value_parser = value_is_date,
+        requires = "diagnosis_from",
+        help = "Diagnosedatum bis (einschließlich) statt Diagnosejahr"
+    )]
+    pub diagnosis_to: Option<String>,
+}
+
 #[derive(Subcommand)]
 pub enum SubCommand {
     #[command(about = "Ermittelt die Prüfwerte aus einem CSV-File für OPAL")]
@@ -39,144 +207,262 @@ pub enum SubCommand {
     },
     #[command(about = "Ermittelt die Prüfwerte aus der Onkostar-Datenbank")]
     Database {
-        #[arg(short = 'D', long, help = "Datenbank-Name", default_value = "onkostar")]
-        database: String,
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(long, help = "Snapshot-Datei statt Datenbankverbindung verwenden")]
+        snapshot: Option<PathBuf>,
+        #[command(flatten)]
+        diagnosis: DiagnosisArgs,
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[arg(long, help = "Meldungen mit oBDS-Schema-version anzeigen")]
+        schema_versions: bool,
+        #[arg(long, help = "Nicht berücksichtigte Meldungen mit Grund anzeigen")]
+        show_excluded: bool,
+    },
+    #[command(
+        about = "Erstellt eine (reduzierte) CSV-Datei zum direkten Vergleich mit der OPAL-CSV-Datei"
+    )]
+    Export {
+        #[arg(long, help = "Export mit Klartext-Patienten-ID")]
+        pat_id: bool,
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(long, help = "Snapshot-Datei statt Datenbankverbindung verwenden")]
+        snapshot: Option<PathBuf>,
+        #[arg(short = 'o', long, help = "Ausgabedatei")]
+        output: PathBuf,
+        #[command(flatten)]
+        diagnosis: DiagnosisArgs,
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[arg(long, help = "Export mit Trennzeichen ';' für Excel")]
+        xls_csv: bool,
+    },
+    #[command(about = "Abgleich zwischen CSV-Datei für OPAL und Onkostar-Datenbank")]
+    Compare {
+        #[arg(long, help = "Klartext-Patienten-ID anzeigen")]
+        pat_id: bool,
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(long, help = "Snapshot-Datei statt Datenbankverbindung verwenden")]
+        snapshot: Option<PathBuf>,
+        #[arg(short, long, help = "CSV-File für Opal")]
+        file: PathBuf,
+        #[command(flatten)]
+        diagnosis: DiagnosisArgs,
+        #[command(flatten)]
+        filter: FilterArgs,
         #[arg(
-            short = 'h',
             long,
-            help = "Datenbank-Host",
-            default_value = "localhost"
+            help = "Maximale Anzahl fehlender Conditions ohne Fehlerstatus",
+            default_value = "0"
         )]
-        host: String,
-        #[arg(short = 'P', long, help = "Datenbank-Host", default_value = "3306")]
-        port: u16,
+        max_missing: usize,
         #[arg(
-            short = 'p',
             long,
-            help = "Passwort. Wenn nicht angegeben, wird danach gefragt"
+            alias = "max-icd-diff",
+            help = "Maximale Anzahl an Conditions mit abweichenden Angaben ohne Fehlerstatus",
+            default_value = "0"
         )]
-        password: Option<String>,
-        #[arg(short = 'u', long, help = "Benutzername")]
-        user: String,
-        #[arg(short = 'y', long, help = "Jahr der Diagnose")]
-        year: String,
-        #[arg(long, value_parser = value_is_date, help = "Ignoriere LKR-Exporte seit Datum")]
-        ignore_exports_since: Option<String>,
-        #[arg(long, help = "Ignoriere Meldungen, die nicht im oBDS 2.x Format sind")]
-        ignore_non_obds_2: bool,
-        #[arg(long, help = "Meldungen mit externer Diagnose einschließen")]
-        include_extern: bool,
+        max_changed: usize,
         #[arg(
             long,
-            help = "Meldungen mit Meldeanlass 'histologie_zytologie' einschließen"
+            help = "Verzeichnis, in das die Abweichungen als CSV-Dateien geschrieben werden"
         )]
-        include_histo_zyto: bool,
-        #[arg(long, help = "Meldungen mit oBDS-Schema-version anzeigen")]
-        schema_versions: bool,
+        output_dir: Option<PathBuf>,
+        #[arg(long, help = "CSV-Dateien mit Trennzeichen ';' für Excel")]
+        xls_csv: bool,
+        #[arg(
+            long,
+            help = "Baseline-Datei mit bekannten Abweichungen, die ausgeblendet werden"
+        )]
+        baseline: Option<PathBuf>,
     },
     #[command(
-        about = "Erstellt eine (reduzierte) CSV-Datei zum direkten Vergleich mit der OPAL-CSV-Datei"
+        about = "Erstellt oder aktualisiert eine Baseline-Datei mit den aktuellen Abweichungen zwischen CSV-Datei für OPAL und Onkostar-Datenbank"
     )]
-    Export {
-        #[arg(long, help = "Export mit Klartext-Patienten-ID")]
+    Baseline {
+        #[arg(long, help = "Klartext-Patienten-ID anzeigen")]
         pat_id: bool,
-        #[arg(short = 'D', long, help = "Datenbank-Name", default_value = "onkostar")]
-        database: String,
-        #[arg(
-            short = 'h',
-            long,
-            help = "Datenbank-Host",
-            default_value = "localhost"
-        )]
-        host: String,
-        #[arg(short = 'P', long, help = "Datenbank-Host", default_value = "3306")]
-        port: u16,
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(long, help = "Snapshot-Datei statt Datenbankverbindung verwenden")]
+        snapshot: Option<PathBuf>,
+        #[arg(short, long, help = "CSV-File für Opal")]
+        file: PathBuf,
+        #[command(flatten)]
+        diagnosis: DiagnosisArgs,
+        #[command(flatten)]
+        filter: FilterArgs,
         #[arg(
-            short = 'p',
+            short = 'o',
             long,
-            help = "Passwort. Wenn nicht angegeben, wird danach gefragt"
+            help = "Baseline-Datei. Bereits enthaltene Einträge bleiben unverändert"
         )]
-        password: Option<String>,
-        #[arg(short = 'u', long, help = "Benutzername")]
-        user: String,
-        #[arg(short = 'o', long, help = "Ausgabedatei")]
         output: PathBuf,
-        #[arg(short = 'y', long, help = "Jahr der Diagnose")]
-        year: String,
-        #[arg(long, value_parser = value_is_date, help = "Ignoriere LKR-Exporte seit Datum")]
-        ignore_exports_since: Option<String>,
-        #[arg(long, help = "Ignoriere Meldungen, die nicht im oBDS 2.x Format sind")]
-        ignore_non_obds_2: bool,
-        #[arg(long, help = "Export mit Trennzeichen ';' für Excel")]
-        xls_csv: bool,
-        #[arg(long, help = "Meldungen mit externer Diagnose einschließen")]
-        include_extern: bool,
+        #[arg(long, help = "Begründung für neue Einträge")]
+        reason: String,
         #[arg(
             long,
-            help = "Meldungen mit Meldeanlass 'histologie_zytologie' einschließen"
+            value_parser = value_is_date,
+            help = "Ablaufdatum neuer Einträge im Format 'yyyy-mm-dd'"
         )]
-        include_histo_zyto: bool,
+        expires: Option<String>,
     },
-    #[command(about = "Abgleich zwischen CSV-Datei für OPAL und Onkostar-Datenbank")]
-    Compare {
+    #[command(
+        about = "Abgleich zwischen CSV-Datei für OPAL und LKR-Export-Protokoll ohne Datenbankzugriff"
+    )]
+    CompareProtocol {
         #[arg(long, help = "Klartext-Patienten-ID anzeigen")]
         pat_id: bool,
-        #[arg(short = 'D', long, help = "Datenbank-Name", default_value = "onkostar")]
-        database: String,
         #[arg(
-            short = 'h',
             long,
-            help = "Datenbank-Host",
-            default_value = "localhost"
+            help = "LKR-Export-Protokoll-Datei oder Verzeichnis mit LKR-Export-Protokoll-Dateien"
         )]
-        host: String,
-        #[arg(short = 'P', long, help = "Datenbank-Host", default_value = "3306")]
-        port: u16,
+        protocol_file: PathBuf,
+        #[arg(short, long, help = "CSV-File für Opal")]
+        file: PathBuf,
+        #[command(flatten)]
+        diagnosis: DiagnosisArgs,
+        #[command(flatten)]
+        filter: FilterArgs,
         #[arg(
-            short = 'p',
             long,
-            help = "Passwort. Wenn nicht angegeben, wird danach gefragt"
+            help = "Maximale Anzahl fehlender Conditions ohne Fehlerstatus",
+            default_value = "0"
         )]
-        password: Option<String>,
-        #[arg(short = 'u', long, help = "Benutzername")]
-        user: String,
+        max_missing: usize,
+        #[arg(
+            long,
+            alias = "max-icd-diff",
+            help = "Maximale Anzahl an Conditions mit abweichenden Angaben ohne Fehlerstatus",
+            default_value = "0"
+        )]
+        max_changed: usize,
+        #[arg(
+            long,
+            help = "Verzeichnis, in das die Abweichungen als CSV-Dateien geschrieben werden"
+        )]
+        output_dir: Option<PathBuf>,
+        #[arg(long, help = "CSV-Dateien mit Trennzeichen ';' für Excel")]
+        xls_csv: bool,
+    },
+    #[command(
+        about = "Abgleich der Anzahl der Conditions nach ICD-10-Gruppe zwischen CSV-Datei für OPAL und Onkostar-Datenbank"
+    )]
+    CompareCounts {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(long, help = "Snapshot-Datei statt Datenbankverbindung verwenden")]
+        snapshot: Option<PathBuf>,
         #[arg(short, long, help = "CSV-File für Opal")]
         file: PathBuf,
         #[arg(short = 'y', long, help = "Jahr der Diagnose")]
         year: String,
-        #[arg(long, value_parser = value_is_date, help = "Ignoriere LKR-Exporte seit Datum")]
-        ignore_exports_since: Option<String>,
-        #[arg(long, help = "Ignoriere Meldungen, die nicht im oBDS 2.x Format sind")]
-        ignore_non_obds_2: bool,
-        #[arg(long, help = "Meldungen mit externer Diagnose einschließen")]
-        include_extern: bool,
+        #[command(flatten)]
+        filter: FilterArgs,
         #[arg(
             long,
-            help = "Meldungen mit Meldeanlass 'histologie_zytologie' einschließen"
+            help = "Tolerierte relative Abweichung in Prozent",
+            default_value = "0"
         )]
-        include_histo_zyto: bool,
+        tolerance: f64,
     },
-    #[command(about = "Abgleich zwischen LKR-Export-Protokoll und Onkostar-Datenbank")]
-    CheckExport {
-        #[arg(short = 'D', long, help = "Datenbank-Name", default_value = "onkostar")]
-        database: String,
+    #[command(about = "Erstellt einen HTML-Bericht über die gesamte Plausibilitätsprüfung")]
+    Report {
+        #[arg(long, help = "Klartext-Patienten-ID anzeigen")]
+        pat_id: bool,
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(long, help = "Snapshot-Datei statt Datenbankverbindung verwenden")]
+        snapshot: Option<PathBuf>,
+        #[arg(short, long, help = "CSV-File für Opal")]
+        file: PathBuf,
+        #[arg(short = 'o', long, help = "Ausgabedatei")]
+        output: PathBuf,
+        #[arg(short = 'y', long, help = "Jahr der Diagnose")]
+        year: String,
+        #[command(flatten)]
+        filter: FilterArgs,
         #[arg(
-            short = 'h',
             long,
-            help = "Datenbank-Host",
-            default_value = "localhost"
+            help = "Tolerierte relative Abweichung in Prozent",
+            default_value = "0"
         )]
-        host: String,
-        #[arg(short = 'P', long, help = "Datenbank-Host", default_value = "3306")]
-        port: u16,
+        tolerance: f64,
         #[arg(
-            short = 'p',
             long,
-            help = "Passwort. Wenn nicht angegeben, wird danach gefragt"
+            help = "LKR-Export-Protokoll-Datei für zusätzlichen Abgleich mit der Datenbank"
         )]
-        password: Option<String>,
-        #[arg(short = 'u', long, help = "Benutzername")]
-        user: String,
+        protocol_file: Option<PathBuf>,
+        #[arg(
+            long,
+            alias = "export-package",
+            help = "Exportpaketnummer",
+            default_value = "0"
+        )]
+        package: u16,
+        #[arg(
+            long,
+            help = "Baseline-Datei mit bekannten Abweichungen, die ausgeblendet werden"
+        )]
+        baseline: Option<PathBuf>,
+    },
+    #[command(
+        alias = "overview",
+        about = "Übersicht der Anzahl der Conditions nach ICD-10-Gruppe über mehrere Diagnosejahre"
+    )]
+    Trend {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(long, help = "Snapshot-Datei statt Datenbankverbindung verwenden")]
+        snapshot: Option<PathBuf>,
+        #[arg(long, help = "Erstes Jahr der Diagnose")]
+        from_year: String,
+        #[arg(long, help = "Letztes Jahr der Diagnose")]
+        to_year: String,
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[arg(
+            long,
+            help = "Relative Änderung zum Vorjahr in Prozent, ab der eine Gruppe hervorgehoben wird",
+            default_value = "50"
+        )]
+        highlight: f64,
+    },
+    #[command(
+        about = "Zeigt, welche Regeln Meldungen einer Condition von der Auswahl ausschließen"
+    )]
+    Explain {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(long, help = "Snapshot-Datei statt Datenbankverbindung verwenden")]
+        snapshot: Option<PathBuf>,
+        #[arg(long, help = "Condition-ID")]
+        cond_id: String,
+        #[arg(
+            short = 'y',
+            long,
+            required_unless_present = "patient_id",
+            help = "Jahr der Diagnose. Ohne Angabe werden alle Meldungen des Patienten durchsucht"
+        )]
+        year: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "year",
+            help = "Patienten-ID der Condition zur Suche in allen Diagnosejahren"
+        )]
+        patient_id: Option<String>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    #[command(about = "Abgleich zwischen LKR-Export-Protokoll und Onkostar-Datenbank")]
+    CheckExport {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(long, help = "Snapshot-Datei statt Datenbankverbindung verwenden")]
+        snapshot: Option<PathBuf>,
         #[arg(short, long, help = "LKR-Export-Protokoll-Datei")]
         file: PathBuf,
         #[arg(
@@ -186,10 +472,58 @@ pub enum SubCommand {
             default_value = "0"
         )]
         package: u16,
+        #[arg(
+            long,
+            help = "Geänderte, hinzugefügte und entfernte Elemente und Attribute abweichender Meldungen anzeigen"
+        )]
+        show_diff: bool,
+        #[arg(
+            long,
+            requires = "show_diff",
+            help = "Verzeichnis, in dem beide Versionen abweichender Meldungen gespeichert werden"
+        )]
+        diff_dir: Option<PathBuf>,
+    },
+    #[command(about = "Lokale Kopie der LKR-Exporte zur Auswertung ohne Datenbankzugriff")]
+    Snapshot {
+        #[command(subcommand)]
+        cmd: SnapshotCommand,
+    },
+}
+
+impl SubCommand {
+    /// Single year of diagnosis, if used by the command
+    pub fn year(&self) -> Option<&str> {
+        match self {
+            SubCommand::CompareCounts { year, .. } | SubCommand::Report { year, .. } => Some(year),
+            // All years use the same version to keep groups comparable
+            SubCommand::Trend { to_year, .. } => Some(to_year),
+            SubCommand::Explain { year, .. } => year.as_deref(),
+            // Each diagnosis period selects its own version
+            SubCommand::Database { .. }
+            | SubCommand::Export { .. }
+            | SubCommand::Compare { .. }
+            | SubCommand::Baseline { .. }
+            | SubCommand::CompareProtocol { .. }
+            | SubCommand::OpalFile { .. }
+            | SubCommand::CheckExport { .. }
+            | SubCommand::Snapshot { .. } => None,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotCommand {
+    #[command(about = "Erstellt eine Snapshot-Datei aus der Onkostar-Datenbank")]
+    Create {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(short, long, help = "Ausgabedatei")]
+        output: PathBuf,
     },
 }
 
-fn value_is_date(value: &str) -> Result<String, String> {
+pub fn value_is_date(value: &str) -> Result<String, String> {
     let re = Regex::new(r"^[0-9]{4}-[0-1][0-9]-[0-3][0-9]$").unwrap();
     if re.is_match(value) {
         Ok(value.into())
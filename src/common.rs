@@ -18,29 +18,198 @@
  * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
  */
 
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+
+use chrono::Datelike;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
+use crate::compare::{self, Comparable, FieldChange};
+use crate::filter::diagnosis_period;
+use crate::icd_groups::{IcdGroups, OTHER};
+
+#[derive(Serialize)]
 pub struct Icd10GroupSize {
     pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub schema_version: Option<String>,
     pub size: usize,
 }
 
+#[derive(Serialize)]
+pub struct Icd10GroupSizeDiff {
+    pub name: String,
+    pub csv_size: usize,
+    pub db_size: usize,
+}
+
+impl Icd10GroupSizeDiff {
+    /// Absolute difference of the CSV count compared to the database count
+    pub fn delta(&self) -> i64 {
+        self.csv_size as i64 - self.db_size as i64
+    }
+
+    /// Relative difference in percent based on the database count.
+    /// Returns `None` if there are no conditions in the database for this group.
+    pub fn relative_delta(&self) -> Option<f64> {
+        if self.db_size == 0 {
+            return None;
+        }
+        Some(self.delta() as f64 * 100.0 / self.db_size as f64)
+    }
+
+    /// Checks if the relative difference exceeds the given tolerance in percent
+    pub fn exceeds(&self, tolerance: f64) -> bool {
+        match self.relative_delta() {
+            Some(relative_delta) => relative_delta.abs() > tolerance,
+            None => self.csv_size > 0,
+        }
+    }
+}
+
+/// Number of conditions of one ICD-10 group in consecutive diagnosis years
+#[derive(Serialize)]
+pub struct Icd10GroupTrend {
+    pub name: String,
+    pub sizes: Vec<usize>,
+}
+
+impl Icd10GroupTrend {
+    /// Sums up the sizes of given groups year by year
+    pub fn sum<'a>(
+        name: &str,
+        items: impl Iterator<Item = &'a Icd10GroupTrend>,
+        years: usize,
+    ) -> Self {
+        Icd10GroupTrend {
+            name: name.to_string(),
+            sizes: items.fold(vec![0; years], |mut sizes, item| {
+                sizes
+                    .iter_mut()
+                    .zip(&item.sizes)
+                    .for_each(|(size, item_size)| *size += item_size);
+                sizes
+            }),
+        }
+    }
+
+    /// Relative change in percent compared to the previous year. Returns `None` for the first
+    /// year and years following a year without conditions in this group.
+    pub fn relative_changes(&self) -> Vec<Option<f64>> {
+        std::iter::once(None)
+            .chain(
+                self.sizes
+                    .iter()
+                    .tuple_windows()
+                    .map(|(&previous, &size)| match previous {
+                        0 => None,
+                        previous => Some((size as f64 - previous as f64) * 100.0 / previous as f64),
+                    }),
+            )
+            .take(self.sizes.len())
+            .collect()
+    }
+}
+
 pub struct Record {
     pub condition_id: String,
     pub icd10_code: String,
 }
 
+/// Kind of difference between a condition in the CSV file and in the database
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Difference {
+    Icd10Code,
+    Icd10Group,
+    DiagnosisDate,
+    DiagnosisYear,
+    Patient,
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Icd10Code => write!(f, "ICD10-Code"),
+            Difference::Icd10Group => write!(f, "ICD-10-Gruppe"),
+            Difference::DiagnosisDate => write!(f, "Diagnosedatum"),
+            Difference::DiagnosisYear => write!(f, "Diagnosejahr"),
+            Difference::Patient => write!(f, "Patient"),
+        }
+    }
+}
+
+impl Difference {
+    /// Identifier as used in JSON output
+    pub fn name(&self) -> String {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(name)) => name,
+            _ => String::new(),
+        }
+    }
+}
+
+/// Condition in the CSV file and in the database with different values.
+/// A changed ICD-10 group or diagnosis year is reported in addition to the changed code or date.
 pub struct DiffRecord {
-    pub pat_id: Option<String>,
     pub condition_id: String,
-    pub diagnosis_date: String,
     pub csv_icd10_code: String,
     pub db_icd10_code: String,
+    pub csv_diagnosis_date: String,
+    pub db_diagnosis_date: String,
+    pub csv_pat_id: Option<String>,
+    pub db_pat_id: Option<String>,
+    pub differences: Vec<Difference>,
+}
+
+impl DiffRecord {
+    /// Patient ID from database or, if not available, from CSV file
+    pub fn pat_id(&self) -> Option<&str> {
+        self.db_pat_id.as_deref().or(self.csv_pat_id.as_deref())
+    }
+
+    /// Changed values with the most significant kind of difference, CSV value first
+    pub fn changed_values(&self) -> Vec<(Difference, &str, &str)> {
+        let has = |difference| self.differences.contains(&difference);
+        let mut values = vec![];
+        if has(Difference::Icd10Code) {
+            values.push((
+                match has(Difference::Icd10Group) {
+                    true => Difference::Icd10Group,
+                    false => Difference::Icd10Code,
+                },
+                self.csv_icd10_code.as_str(),
+                self.db_icd10_code.as_str(),
+            ));
+        }
+        if has(Difference::DiagnosisDate) {
+            values.push((
+                match has(Difference::DiagnosisYear) {
+                    true => Difference::DiagnosisYear,
+                    false => Difference::DiagnosisDate,
+                },
+                self.csv_diagnosis_date.as_str(),
+                self.db_diagnosis_date.as_str(),
+            ));
+        }
+        if has(Difference::Patient) {
+            values.push((
+                Difference::Patient,
+                self.csv_pat_id.as_deref().unwrap_or_default(),
+                self.db_pat_id.as_deref().unwrap_or_default(),
+            ));
+        }
+        values
+    }
+
+    /// Checks if only the ICD-10 code changed within the same ICD-10 group
+    pub fn is_same_group_only(&self) -> bool {
+        self.differences == [Difference::Icd10Code]
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ExportData {
     #[serde(rename = "pat_id")]
     pub pat_id: Option<String>,
@@ -52,16 +221,109 @@ pub struct ExportData {
     pub icd_10_code: String,
 }
 
+impl Comparable for ExportData {
+    fn key(&self) -> &str {
+        &self.condition_id
+    }
+
+    fn fields(&self) -> Vec<(&'static str, &str)> {
+        vec![
+            ("condcodingcode", &self.icd_10_code),
+            ("conditiondate", &self.diagnosis_date),
+            ("pat_id", self.pat_id.as_deref().unwrap_or_default()),
+        ]
+    }
+
+    /// Diagnosis dates are compared independent of their format, a partial date matches all dates
+    /// within its month or year. Patient IDs are compared only if present on both sides.
+    fn same_value(name: &str, left: &str, right: &str) -> bool {
+        match name {
+            "conditiondate" => match (diagnosis_period(left), diagnosis_period(right)) {
+                (Some((left_first, left_last)), Some((right_first, right_last))) => {
+                    (left_first <= right_first && right_last <= left_last)
+                        || (right_first <= left_first && left_last <= right_last)
+                }
+                // Unknown formats can only be compared as text
+                _ => left == right,
+            },
+            "pat_id" => left.is_empty() || right.is_empty() || left == right,
+            _ => left == right,
+        }
+    }
+}
+
+/// Row of `lkr_meldung_export` including the export date of the related `lkr_export`
+#[derive(Serialize, Deserialize)]
+pub struct MeldungExport {
+    pub id: u64,
+    pub lkr_meldung: Option<u64>,
+    pub lkr_export: Option<u64>,
+    pub typ: Option<i64>,
+    pub versionsnummer: Option<u64>,
+    pub xml_daten: String,
+    pub exportiert_am: Option<String>,
+}
+
+/// Conditions which differ between database and CSV file
+pub struct ConditionDiffs<'a> {
+    pub not_in_csv: Vec<&'a ExportData>,
+    pub not_in_db: Vec<&'a ExportData>,
+    pub changed: Vec<DiffRecord>,
+    /// Number of discrepancies hidden by the baseline
+    pub suppressed: usize,
+}
+
+impl ConditionDiffs<'_> {
+    pub fn has_discrepancies(&self) -> bool {
+        !self.not_in_csv.is_empty() || !self.not_in_db.is_empty() || !self.changed.is_empty()
+    }
+
+    /// Hides discrepancies of the given conditions and counts them as suppressed
+    pub fn suppress(&mut self, condition_ids: &HashSet<String>) {
+        let count = self.not_in_csv.len() + self.not_in_db.len() + self.changed.len();
+
+        self.not_in_csv
+            .retain(|item| !condition_ids.contains(&item.condition_id));
+        self.not_in_db
+            .retain(|item| !condition_ids.contains(&item.condition_id));
+        self.changed
+            .retain(|item| !condition_ids.contains(&item.condition_id));
+
+        self.suppressed +=
+            count - self.not_in_csv.len() - self.not_in_db.len() - self.changed.len();
+    }
+
+    /// Condition IDs of all discrepancies
+    pub fn condition_ids(&self) -> Vec<&str> {
+        self.not_in_csv
+            .iter()
+            .chain(self.not_in_db.iter())
+            .map(|item| item.condition_id.as_str())
+            .chain(self.changed.iter().map(|item| item.condition_id.as_str()))
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// Number of changed conditions with a different ICD-10 code within the same ICD-10 group only
+    pub fn same_group_only(&self) -> usize {
+        self.changed
+            .iter()
+            .filter(|item| item.is_same_group_only())
+            .count()
+    }
+}
+
 pub struct Check;
 
 impl Check {
     /// Collects all records by grouping by ICD10 group
-    pub fn collect(records: &[Record]) -> Result<Vec<Icd10GroupSize>, ()> {
-        let items = records
+    pub fn collect(records: &[Record], icd_groups: &IcdGroups) -> Vec<Icd10GroupSize> {
+        records
             .iter()
             .map(|record| Record {
                 condition_id: record.condition_id.to_string(),
-                icd10_code: Self::map_icd_code(&record.icd10_code),
+                icd10_code: Self::map_icd_code(&record.icd10_code, icd_groups),
             })
             .sorted_by_key(|record| record.icd10_code.to_string())
             .chunk_by(|record| record.icd10_code.to_string())
@@ -72,61 +334,317 @@ impl Check {
                 schema_version: None,
                 size: record.1.len(),
             })
-            .collect::<Vec<_>>();
+            .collect::<Vec<_>>()
+    }
 
-        Ok(items)
+    /// Joins group sizes from CSV file and database by ICD10 group
+    pub fn compare_sizes(
+        csv_items: &[Icd10GroupSize],
+        db_items: &[Icd10GroupSize],
+    ) -> Vec<Icd10GroupSizeDiff> {
+        let size_of = |items: &[Icd10GroupSize], name: &str| -> usize {
+            items
+                .iter()
+                .filter(|item| item.name == name)
+                .map(|item| item.size)
+                .sum()
+        };
+
+        csv_items
+            .iter()
+            .chain(db_items.iter())
+            .map(|item| item.name.to_string())
+            .unique()
+            .sorted()
+            .map(|name| Icd10GroupSizeDiff {
+                csv_size: size_of(csv_items, &name),
+                db_size: size_of(db_items, &name),
+                name,
+            })
+            .collect()
     }
 
-    pub fn is_relevant(code: &str) -> bool {
-        !matches!(Self::map_icd_code(code).as_str(), "Other")
+    /// Joins group sizes of consecutive diagnosis years by ICD10 group
+    pub fn trend(sizes_by_year: &[Vec<Icd10GroupSize>]) -> Vec<Icd10GroupTrend> {
+        sizes_by_year
+            .iter()
+            .flatten()
+            .map(|item| item.name.to_string())
+            .unique()
+            .sorted()
+            .map(|name| Icd10GroupTrend {
+                sizes: sizes_by_year
+                    .iter()
+                    .map(|items| {
+                        items
+                            .iter()
+                            .filter(|item| item.name == name)
+                            .map(|item| item.size)
+                            .sum()
+                    })
+                    .collect(),
+                name,
+            })
+            .collect()
     }
 
-    fn map_icd_code(code: &str) -> String {
-        let icd10 = match code {
-            "D39.1" | "D09.0" | "D41.4" => code,
-            _ => code.split('.').collect::<Vec<_>>().first().unwrap(),
-        };
+    /// Compares conditions from database and CSV file by condition ID, ICD10 code,
+    /// diagnosis date and patient
+    pub fn compare<'a>(
+        db_items: &'a [ExportData],
+        csv_items: &'a [ExportData],
+        icd_groups: &IcdGroups,
+    ) -> ConditionDiffs<'a> {
+        let comparison = compare::compare(db_items, csv_items);
+
+        ConditionDiffs {
+            not_in_csv: comparison.only_left,
+            not_in_db: comparison.only_right,
+            suppressed: 0,
+            changed: comparison
+                .changed
+                .iter()
+                .map(|changed| {
+                    let (db_item, csv_item) = (changed.left, changed.right);
+                    DiffRecord {
+                        condition_id: db_item.condition_id.to_string(),
+                        csv_icd10_code: csv_item.icd_10_code.to_string(),
+                        db_icd10_code: db_item.icd_10_code.to_string(),
+                        csv_diagnosis_date: csv_item.diagnosis_date.to_string(),
+                        db_diagnosis_date: db_item.diagnosis_date.to_string(),
+                        csv_pat_id: csv_item.pat_id.clone(),
+                        db_pat_id: db_item.pat_id.clone(),
+                        differences: changed
+                            .fields
+                            .iter()
+                            .flat_map(|field| Self::differences(field, icd_groups))
+                            .collect(),
+                    }
+                })
+                .collect(),
+        }
+    }
 
-        match icd10 {
-            "C00" | "C01" | "C02" | "C03" | "C04" | "C05" | "C06" | "C07" | "C08" | "C09"
-            | "C10" | "C11" | "C12" | "C13" | "C14" => "C00-C14",
-            "C15" => "C15",
-            "C16" => "C16",
-            "C18" | "C19" | "C20" | "C21" => "C18-C21",
-            "C22" => "C22",
-            "C23" | "C24" => "C23-C24",
-            "C25" => "C25",
-            "C32" => "C32",
-            "C33" | "C34" => "C33-C34",
-            "C43" => "C43",
-            "C50" | "D05" => "C50, D05",
-            "C53" | "D06" => "C53, D06",
-            "C54" | "C55" => "C54-C55",
-            "C56" | "D39.1" => "C56, D39.1",
-            "C61" => "C61",
-            "C62" => "C62",
-            "C64" => "C64",
-            "C67" | "D09.0" | "D41.4" => "C67, D09.0, D41.4",
-            "C70" | "C71" | "C72" => "C70-C72",
-            "C73" => "C73",
-            "C81" => "C81",
-            "C82" | "C83" | "C84" | "C85" | "C86" | "C87" | "C88" | "C96" => "C82-C88, C96",
-            "C90" => "C90",
-            "C91" | "C92" | "C93" | "C94" | "C95" => "C91-C95",
-            _ => "Other",
+    /// Kinds of difference of a changed field with database value on the left side
+    fn differences(field: &FieldChange, icd_groups: &IcdGroups) -> Vec<Difference> {
+        match field.name {
+            "condcodingcode" => {
+                match icd_groups.group_name(field.left) == icd_groups.group_name(field.right) {
+                    true => vec![Difference::Icd10Code],
+                    false => vec![Difference::Icd10Code, Difference::Icd10Group],
+                }
+            }
+            "conditiondate" => {
+                let year = |date| diagnosis_period(date).map(|(from, _)| from.year());
+                match (year(field.left), year(field.right)) {
+                    (Some(left), Some(right)) if left != right => {
+                        vec![Difference::DiagnosisDate, Difference::DiagnosisYear]
+                    }
+                    _ => vec![Difference::DiagnosisDate],
+                }
+            }
+            _ => vec![Difference::Patient],
         }
-        .to_string()
+    }
+
+    pub fn is_relevant(code: &str, icd_groups: &IcdGroups) -> bool {
+        icd_groups.group_name(code) != OTHER
+    }
+
+    pub fn map_icd_code(code: &str, icd_groups: &IcdGroups) -> String {
+        icd_groups.group_name(code).to_string()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::common::Check;
+    use std::collections::HashSet;
+
+    use crate::common::{Check, Difference, ExportData, Icd10GroupSize, Icd10GroupTrend};
+    use crate::icd_groups::IcdGroups;
+
+    fn group_size(name: &str, size: usize) -> Icd10GroupSize {
+        Icd10GroupSize {
+            name: name.into(),
+            schema_version: None,
+            size,
+        }
+    }
+
+    fn export_data(condition_id: &str, date: &str, code: &str, pat_id: Option<&str>) -> ExportData {
+        ExportData {
+            pat_id: pat_id.map(|pat_id| pat_id.into()),
+            condition_id: condition_id.into(),
+            diagnosis_date: date.into(),
+            icd_10_code: code.into(),
+        }
+    }
+
+    #[test]
+    fn should_compare_conditions_by_field() {
+        let db_items = vec![
+            export_data("1", "10.06.2024", "C17.1", Some("1001")),
+            export_data("2", "10.06.2024", "C17.1", Some("1002")),
+            export_data("3", "00.06.2024", "C50.1", Some("1003")),
+            export_data("4", "10.06.2024", "C61", None),
+            export_data("5", "10.06.2024", "C61", None),
+        ];
+        let csv_items = vec![
+            export_data("1", "2024-06-10", "C17.2", None),
+            export_data("2", "2024-06-10", "C50.1", None),
+            export_data("3", "2023-06-01", "C50.1", Some("2003")),
+            export_data("4", "2024-06-10", "C61", Some("2004")),
+            export_data("6", "2024-06-10", "C61", None),
+        ];
+
+        let actual = Check::compare(&db_items, &csv_items, &IcdGroups::default());
+
+        assert_eq!(actual.not_in_csv.len(), 1);
+        assert_eq!(actual.not_in_csv[0].condition_id, "5");
+        assert_eq!(actual.not_in_db.len(), 1);
+        assert_eq!(actual.not_in_db[0].condition_id, "6");
+        assert_eq!(actual.changed.len(), 3);
+        assert_eq!(actual.changed[0].differences, vec![Difference::Icd10Code]);
+        assert_eq!(actual.changed[0].csv_icd10_code, "C17.2");
+        assert_eq!(actual.changed[0].db_icd10_code, "C17.1");
+        assert_eq!(
+            actual.changed[1].differences,
+            vec![Difference::Icd10Code, Difference::Icd10Group]
+        );
+        assert_eq!(
+            actual.changed[2].differences,
+            vec![
+                Difference::DiagnosisDate,
+                Difference::DiagnosisYear,
+                Difference::Patient
+            ]
+        );
+        assert_eq!(actual.same_group_only(), 1);
+    }
+
+    #[test]
+    fn should_match_partial_diagnosis_date_with_date_within_period() {
+        let db_items = vec![
+            export_data("1", "00.06.2024", "C17.1", None),
+            export_data("2", "00.06.2024", "C17.1", None),
+            export_data("3", "00.00.2024", "C17.1", None),
+        ];
+        let csv_items = vec![
+            export_data("1", "2024-06-15", "C17.1", None),
+            export_data("2", "2024-07-15", "C17.1", None),
+            export_data("3", "15.06.2024", "C17.1", None),
+        ];
+
+        let actual = Check::compare(&db_items, &csv_items, &IcdGroups::default());
+
+        assert_eq!(actual.changed.len(), 1);
+        assert_eq!(actual.changed[0].condition_id, "2");
+        assert_eq!(
+            actual.changed[0].differences,
+            vec![Difference::DiagnosisDate]
+        );
+    }
+
+    #[test]
+    fn should_use_json_identifiers_as_names() {
+        assert_eq!(Difference::Icd10Code.name(), "icd10_code");
+        assert_eq!(Difference::DiagnosisYear.name(), "diagnosis_year");
+        assert_eq!(Difference::Patient.name(), "patient");
+    }
+
+    #[test]
+    fn should_suppress_known_conditions() {
+        let db_items = vec![
+            export_data("1", "10.06.2024", "C17.1", None),
+            export_data("2", "10.06.2024", "C17.1", None),
+        ];
+        let csv_items = vec![
+            export_data("2", "10.06.2024", "C17.2", None),
+            export_data("3", "10.06.2024", "C61", None),
+        ];
+
+        let mut actual = Check::compare(&db_items, &csv_items, &IcdGroups::default());
+        assert_eq!(actual.condition_ids(), vec!["1", "2", "3"]);
+
+        actual.suppress(&HashSet::from(["1".to_string(), "2".to_string()]));
+
+        assert!(actual.not_in_csv.is_empty());
+        assert!(actual.changed.is_empty());
+        assert_eq!(actual.not_in_db.len(), 1);
+        assert_eq!(actual.suppressed, 2);
+    }
 
     #[test]
     fn should_map_icd10_code_as_expected() {
-        assert_eq!(Check::map_icd_code("D39.1"), "C56, D39.1");
-        assert_eq!(Check::map_icd_code("C00"), "C00-C14");
-        assert_eq!(Check::map_icd_code("F79.9"), "Other");
+        let icd_groups = IcdGroups::default();
+
+        assert_eq!(Check::map_icd_code("D39.1", &icd_groups), "C56, D39.1");
+        assert_eq!(Check::map_icd_code("C00", &icd_groups), "C00-C14");
+        assert_eq!(Check::map_icd_code("F79.9", &icd_groups), "Other");
+    }
+
+    #[test]
+    fn should_compare_group_sizes() {
+        let csv_items = vec![
+            Icd10GroupSize {
+                name: "C15".into(),
+                schema_version: None,
+                size: 95,
+            },
+            Icd10GroupSize {
+                name: "C16".into(),
+                schema_version: None,
+                size: 10,
+            },
+        ];
+        let db_items = vec![
+            Icd10GroupSize {
+                name: "C15".into(),
+                schema_version: None,
+                size: 100,
+            },
+            Icd10GroupSize {
+                name: "C61".into(),
+                schema_version: None,
+                size: 5,
+            },
+        ];
+
+        let actual = Check::compare_sizes(&csv_items, &db_items);
+
+        assert_eq!(actual.len(), 3);
+        assert_eq!(actual[0].name, "C15");
+        assert_eq!(actual[0].delta(), -5);
+        assert_eq!(actual[0].relative_delta(), Some(-5.0));
+        assert!(actual[0].exceeds(1.0));
+        assert!(!actual[0].exceeds(5.0));
+        assert_eq!(actual[1].name, "C16");
+        assert_eq!(actual[1].relative_delta(), None);
+        assert!(actual[1].exceeds(100.0));
+        assert_eq!(actual[2].name, "C61");
+        assert_eq!(actual[2].delta(), -5);
+    }
+
+    #[test]
+    fn should_join_group_sizes_by_year() {
+        let actual = Check::trend(&[
+            vec![group_size("C15", 100), group_size("C61", 40)],
+            vec![group_size("C15", 50)],
+            vec![group_size("C15", 60), group_size("C61", 10)],
+        ]);
+
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[0].name, "C15");
+        assert_eq!(actual[0].sizes, vec![100, 50, 60]);
+        assert_eq!(
+            actual[0].relative_changes(),
+            vec![None, Some(-50.0), Some(20.0)]
+        );
+        assert_eq!(actual[1].name, "C61");
+        assert_eq!(actual[1].sizes, vec![40, 0, 10]);
+        assert_eq!(actual[1].relative_changes(), vec![None, Some(-100.0), None]);
+
+        let total = Icd10GroupTrend::sum("Gesamtsumme", actual.iter(), 3);
+        assert_eq!(total.sizes, vec![140, 50, 70]);
     }
 }
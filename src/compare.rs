@@ -0,0 +1,261 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+/// Item identified by a key with named field values to be compared
+pub trait Comparable {
+    fn key(&self) -> &str;
+
+    /// Named values compared if both sides contain the key
+    fn fields(&self) -> Vec<(&'static str, &str)>;
+
+    /// Checks if both values of the named field are considered equal
+    fn same_value(_name: &str, left: &str, right: &str) -> bool {
+        left == right
+    }
+}
+
+/// Field with different values on both sides
+#[derive(Debug, PartialEq)]
+pub struct FieldChange<'a> {
+    pub name: &'static str,
+    pub left: &'a str,
+    pub right: &'a str,
+}
+
+/// Items with the same key on both sides but different field values
+#[derive(Debug)]
+pub struct Changed<'a, T> {
+    pub left: &'a T,
+    pub right: &'a T,
+    pub fields: Vec<FieldChange<'a>>,
+}
+
+/// Result of a comparison, each list sorted by key
+#[derive(Debug)]
+pub struct Comparison<'a, T> {
+    pub only_left: Vec<&'a T>,
+    pub only_right: Vec<&'a T>,
+    pub changed: Vec<Changed<'a, T>>,
+}
+
+/// Compares both sides by key. If a key occurs multiple times on the right side, an item is
+/// unchanged if any of these items has the same field values, otherwise it is compared with the
+/// item having the fewest changed fields.
+pub fn compare<'a, T: Comparable>(left: &'a [T], right: &'a [T]) -> Comparison<'a, T> {
+    let left_index = index(left);
+    let right_index = index(right);
+
+    let only = |items: &'a [T], other: &HashMap<&str, Vec<&'a T>>| {
+        items
+            .iter()
+            .filter(|item| !other.contains_key(item.key()))
+            .sorted_by(|a, b| a.key().cmp(b.key()))
+            .collect_vec()
+    };
+
+    let changed = left
+        .iter()
+        .filter_map(|left_item| {
+            let (right_item, fields) = right_index
+                .get(left_item.key())?
+                .iter()
+                .map(|&right_item| (right_item, changed_fields(left_item, right_item)))
+                .min_by_key(|(_, fields)| fields.len())?;
+            (!fields.is_empty()).then_some(Changed {
+                left: left_item,
+                right: right_item,
+                fields,
+            })
+        })
+        .sorted_by(|a, b| a.left.key().cmp(b.left.key()))
+        .collect_vec();
+
+    Comparison {
+        only_left: only(left, &right_index),
+        only_right: only(right, &left_index),
+        changed,
+    }
+}
+
+fn changed_fields<'a, T: Comparable>(left: &'a T, right: &'a T) -> Vec<FieldChange<'a>> {
+    left.fields()
+        .into_iter()
+        .zip(right.fields())
+        .filter(|((name, left_value), (_, right_value))| {
+            !T::same_value(name, left_value, right_value)
+        })
+        .map(|((name, left_value), (_, right_value))| FieldChange {
+            name,
+            left: left_value,
+            right: right_value,
+        })
+        .collect_vec()
+}
+
+/// Indexes items by key keeping all items of each key in order
+fn index<T: Comparable>(items: &[T]) -> HashMap<&str, Vec<&T>> {
+    let mut index = HashMap::<&str, Vec<&T>>::with_capacity(items.len());
+    for item in items {
+        index.entry(item.key()).or_default().push(item);
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compare::{compare, Comparable, FieldChange};
+
+    struct Item {
+        key: &'static str,
+        code: &'static str,
+        date: &'static str,
+    }
+
+    impl Comparable for Item {
+        fn key(&self) -> &str {
+            self.key
+        }
+
+        fn fields(&self) -> Vec<(&'static str, &str)> {
+            vec![("code", self.code), ("date", self.date)]
+        }
+
+        fn same_value(name: &str, left: &str, right: &str) -> bool {
+            match name {
+                "code" => left.eq_ignore_ascii_case(right),
+                _ => left == right,
+            }
+        }
+    }
+
+    fn item(key: &'static str, code: &'static str, date: &'static str) -> Item {
+        Item { key, code, date }
+    }
+
+    #[test]
+    fn should_compare_items_by_key() {
+        let left = vec![
+            item("3", "C17.1", "2024-01-01"),
+            item("1", "C17.1", "2024-01-01"),
+            item("2", "C50.1", "2024-01-01"),
+        ];
+        let right = vec![
+            item("4", "C61", "2024-01-01"),
+            item("2", "C50.1", "2024-01-01"),
+            item("1", "C17.2", "2024-01-01"),
+        ];
+
+        let actual = compare(&left, &right);
+
+        assert_eq!(
+            actual
+                .only_left
+                .iter()
+                .map(|item| item.key)
+                .collect::<Vec<_>>(),
+            vec!["3"]
+        );
+        assert_eq!(
+            actual
+                .only_right
+                .iter()
+                .map(|item| item.key)
+                .collect::<Vec<_>>(),
+            vec!["4"]
+        );
+        assert_eq!(actual.changed.len(), 1);
+        assert_eq!(actual.changed[0].left.key, "1");
+        assert_eq!(actual.changed[0].right.code, "C17.2");
+        assert_eq!(
+            actual.changed[0].fields,
+            vec![FieldChange {
+                name: "code",
+                left: "C17.1",
+                right: "C17.2"
+            }]
+        );
+    }
+
+    #[test]
+    fn should_report_all_changed_fields_sorted_by_key() {
+        let left = vec![
+            item("2", "C17.1", "2024-01-01"),
+            item("1", "C17.1", "2024-01-01"),
+        ];
+        let right = vec![
+            item("1", "C17.1", "2023-12-31"),
+            item("2", "C17.2", "2023-12-31"),
+        ];
+
+        let actual = compare(&left, &right);
+
+        assert!(actual.only_left.is_empty());
+        assert!(actual.only_right.is_empty());
+        assert_eq!(actual.changed.len(), 2);
+        assert_eq!(actual.changed[0].left.key, "1");
+        assert_eq!(actual.changed[0].fields.len(), 1);
+        assert_eq!(actual.changed[0].fields[0].name, "date");
+        assert_eq!(actual.changed[1].left.key, "2");
+        assert_eq!(actual.changed[1].fields.len(), 2);
+    }
+
+    #[test]
+    fn should_use_field_specific_equality() {
+        let left = vec![item("1", "c17.1", "2024-01-01")];
+        let right = vec![item("1", "C17.1", "2024-01-01")];
+
+        let actual = compare(&left, &right);
+
+        assert!(actual.changed.is_empty());
+    }
+
+    #[test]
+    fn should_compare_with_all_items_of_duplicate_keys() {
+        let left = vec![
+            item("1", "C17.1", "2024-01-01"),
+            item("2", "C17.1", "2024-01-01"),
+        ];
+        let right = vec![
+            item("1", "C17.2", "2024-01-01"),
+            item("1", "C17.1", "2024-01-01"),
+            item("2", "C17.2", "2023-12-31"),
+            item("2", "C17.3", "2024-01-01"),
+        ];
+
+        let actual = compare(&left, &right);
+
+        assert!(actual.only_right.is_empty());
+        assert_eq!(actual.changed.len(), 1);
+        assert_eq!(actual.changed[0].left.key, "2");
+        assert_eq!(actual.changed[0].right.code, "C17.3");
+        assert_eq!(
+            actual.changed[0].fields,
+            vec![FieldChange {
+                name: "code",
+                left: "C17.1",
+                right: "C17.3"
+            }]
+        );
+    }
+}
@@ -0,0 +1,518 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::cli::{value_is_date, DatabaseArgs, FilterArgs};
+use crate::filter::{EntryRules, ExternRule};
+use crate::lkrexport::ConditionIdScheme;
+
+const CONFIG_FILE_NAME: &str = "bzkf-rwdp-check.toml";
+
+/// Content of the optional configuration file
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    pub filter: FilterDefaults,
+    #[serde(default, rename = "extern")]
+    pub extern_rule: ExternDefinition,
+    #[serde(default)]
+    pub condition_id: ConditionIdDefinition,
+}
+
+/// Named database connection profile
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub database: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub socket: Option<String>,
+    pub tls: Option<bool>,
+    pub tls_ca_file: Option<PathBuf>,
+    pub tls_client_identity: Option<PathBuf>,
+    pub tls_client_identity_password: Option<String>,
+    pub tls_verify_hostname: Option<bool>,
+}
+
+/// Default values for filter options
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterDefaults {
+    pub ignore_exports_since: Option<String>,
+    #[serde(default)]
+    pub ignore_non_obds_2: bool,
+    #[serde(default)]
+    pub include_extern: bool,
+    #[serde(default)]
+    pub include_histo_zyto: bool,
+}
+
+/// Rules to detect Meldungen with external diagnosis. Without any rule, `Meldende_Stelle`
+/// containing `9999` is used.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExternDefinition {
+    #[serde(default)]
+    pub meldende_stelle: Vec<String>,
+    #[serde(default)]
+    pub melder_ids: Vec<String>,
+    #[serde(default)]
+    pub absender: Vec<String>,
+}
+
+/// Identifier system and composition of the hashed value of condition IDs
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConditionIdDefinition {
+    pub system: Option<String>,
+    pub pattern: Option<String>,
+}
+
+/// Connection settings after merging command line, environment and configuration file
+pub struct ConnectionSettings {
+    pub database: String,
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: Option<String>,
+    pub socket: Option<String>,
+    pub tls: Option<TlsSettings>,
+}
+
+/// Settings for encrypted connections
+#[derive(Debug, PartialEq)]
+pub struct TlsSettings {
+    pub ca_file: Option<PathBuf>,
+    pub client_identity: Option<PathBuf>,
+    pub client_identity_password: Option<String>,
+    pub verify_hostname: bool,
+}
+
+/// Filter options after merging command line and configuration file
+pub struct Filter {
+    pub ignore_exports_since: Option<String>,
+    pub ignore_non_obds_2: bool,
+    pub include_extern: bool,
+    pub include_histo_zyto: bool,
+}
+
+impl Config {
+    /// Loads the given configuration file or, if not given, the file in the user's
+    /// configuration directory. A missing default configuration file is not an error.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let content = fs::read_to_string(&path).map_err(|_| {
+            format!(
+                "Kann Konfigurationsdatei '{}' nicht lesen",
+                path.to_str().unwrap_or_default()
+            )
+        })?;
+
+        Self::parse(&content).map_err(|err| {
+            format!(
+                "Ungültige Konfigurationsdatei '{}': {}",
+                path.to_str().unwrap_or_default(),
+                err
+            )
+        })
+    }
+
+    fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CONFIG_FILE_NAME))
+    }
+
+    fn parse(content: &str) -> Result<Config, String> {
+        let config = toml::from_str::<Config>(content).map_err(|err| err.message().to_string())?;
+
+        if let Some(ref date) = config.filter.ignore_exports_since {
+            value_is_date(date)?;
+        }
+
+        config.entry_rules()?;
+
+        Ok(config)
+    }
+
+    fn profile(&self, name: Option<&str>) -> Result<Option<&Profile>, String> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok(Some(profile)),
+                None => Err(format!("Profil '{}' nicht gefunden", name)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Merges connection settings. Command line arguments and environment variables take
+    /// precedence over values of the selected profile.
+    pub fn connection_settings(
+        &self,
+        args: &DatabaseArgs,
+        profile: Option<&str>,
+    ) -> Result<ConnectionSettings, String> {
+        let default_profile = Profile::default();
+        let profile = self.profile(profile)?.unwrap_or(&default_profile);
+
+        Ok(ConnectionSettings {
+            database: args
+                .database
+                .as_ref()
+                .or(profile.database.as_ref())
+                .map_or("onkostar".to_string(), |database| database.to_string()),
+            host: args
+                .host
+                .as_ref()
+                .or(profile.host.as_ref())
+                .map_or("localhost".to_string(), |host| host.to_string()),
+            port: args.port.or(profile.port).unwrap_or(3306),
+            user: args
+                .user
+                .as_ref()
+                .or(profile.user.as_ref())
+                .map(|user| user.to_string())
+                .ok_or("Kein Benutzername angegeben")?,
+            password: args
+                .password
+                .as_ref()
+                .or(profile.password.as_ref())
+                .map(|password| password.to_string()),
+            socket: args
+                .socket
+                .as_ref()
+                .or(profile.socket.as_ref())
+                .map(|socket| socket.to_string()),
+            tls: Self::tls_settings(args, profile),
+        })
+    }
+
+    fn tls_settings(args: &DatabaseArgs, profile: &Profile) -> Option<TlsSettings> {
+        let ca_file = args.tls_ca_file.as_ref().or(profile.tls_ca_file.as_ref());
+        let client_identity = args
+            .tls_client_identity
+            .as_ref()
+            .or(profile.tls_client_identity.as_ref());
+
+        let use_tls = args.tls
+            || profile.tls.unwrap_or_default()
+            || ca_file.is_some()
+            || client_identity.is_some();
+
+        if !use_tls {
+            return None;
+        }
+
+        Some(TlsSettings {
+            ca_file: ca_file.cloned(),
+            client_identity: client_identity.cloned(),
+            client_identity_password: args
+                .tls_client_identity_password
+                .as_ref()
+                .or(profile.tls_client_identity_password.as_ref())
+                .cloned(),
+            verify_hostname: !args.tls_skip_verify_hostname
+                && profile.tls_verify_hostname.unwrap_or(true),
+        })
+    }
+
+    /// Site specific rules to read entries
+    pub fn entry_rules(&self) -> Result<EntryRules, String> {
+        Ok(EntryRules {
+            extern_rule: self.extern_rule()?,
+            condition_id: ConditionIdScheme::new(
+                self.condition_id.system.as_deref(),
+                self.condition_id.pattern.as_deref(),
+            )?,
+        })
+    }
+
+    fn extern_rule(&self) -> Result<ExternRule, String> {
+        let definition = &self.extern_rule;
+        if definition.meldende_stelle.is_empty()
+            && definition.melder_ids.is_empty()
+            && definition.absender.is_empty()
+        {
+            return Ok(ExternRule::default());
+        }
+
+        ExternRule::new(
+            &definition.meldende_stelle,
+            &definition.melder_ids,
+            &definition.absender,
+        )
+    }
+
+    /// Merges filter options. Flags given on command line, enabling or disabling, take
+    /// precedence over the configuration file.
+    pub fn filter(&self, args: &FilterArgs) -> Filter {
+        Filter {
+            ignore_exports_since: args
+                .ignore_exports_since
+                .as_ref()
+                .or(self.filter.ignore_exports_since.as_ref())
+                .map(|date| date.to_string()),
+            ignore_non_obds_2: merge_flag(
+                args.ignore_non_obds_2,
+                args.no_ignore_non_obds_2,
+                self.filter.ignore_non_obds_2,
+            ),
+            include_extern: merge_flag(
+                args.include_extern,
+                args.no_include_extern,
+                self.filter.include_extern,
+            ),
+            include_histo_zyto: merge_flag(
+                args.include_histo_zyto,
+                args.no_include_histo_zyto,
+                self.filter.include_histo_zyto,
+            ),
+        }
+    }
+}
+
+fn merge_flag(enabled: bool, disabled: bool, default: bool) -> bool {
+    match (enabled, disabled) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::cli::{DatabaseArgs, FilterArgs};
+    use crate::config::{Config, TlsSettings};
+
+    const CONFIG: &str = r#"
+        default_profile = "test"
+
+        [profiles.prod]
+        host = "onkostar.example.com"
+        user = "prod"
+        password = "secret"
+
+        [profiles.test]
+        host = "onkostar-test.example.com"
+        port = 3307
+        user = "test"
+
+        [profiles.local]
+        socket = "/run/mysqld/mysqld.sock"
+        user = "local"
+        tls_ca_file = "/etc/ssl/ca.pem"
+        tls_verify_hostname = false
+
+        [filter]
+        ignore_exports_since = "2024-01-01"
+        include_extern = true
+
+        [extern]
+        meldende_stelle = ["9999", "^PARTNER"]
+        melder_ids = ["M42"]
+
+        [condition_id]
+        system = "https://fhir.example.org/identifiers/condition-id"
+    "#;
+
+    fn database_args() -> DatabaseArgs {
+        DatabaseArgs {
+            database: None,
+            host: None,
+            port: None,
+            password: None,
+            user: None,
+            socket: None,
+            tls: false,
+            tls_ca_file: None,
+            tls_client_identity: None,
+            tls_client_identity_password: None,
+            tls_skip_verify_hostname: false,
+        }
+    }
+
+    #[test]
+    fn should_use_selected_profile() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let actual = config
+            .connection_settings(&database_args(), Some("prod"))
+            .unwrap();
+
+        assert_eq!(actual.database, "onkostar");
+        assert_eq!(actual.host, "onkostar.example.com");
+        assert_eq!(actual.port, 3306);
+        assert_eq!(actual.user, "prod");
+        assert_eq!(actual.password, Some("secret".to_string()));
+    }
+
+    #[test]
+    fn should_use_default_profile() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let actual = config.connection_settings(&database_args(), None).unwrap();
+
+        assert_eq!(actual.host, "onkostar-test.example.com");
+        assert_eq!(actual.port, 3307);
+        assert_eq!(actual.password, None);
+    }
+
+    #[test]
+    fn should_override_profile_with_args() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let args = DatabaseArgs {
+            host: Some("localhost".into()),
+            user: Some("me".into()),
+            ..database_args()
+        };
+
+        let actual = config.connection_settings(&args, Some("prod")).unwrap();
+
+        assert_eq!(actual.host, "localhost");
+        assert_eq!(actual.user, "me");
+        assert_eq!(actual.password, Some("secret".to_string()));
+    }
+
+    #[test]
+    fn should_fail_on_unknown_profile_or_missing_user() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert!(config
+            .connection_settings(&database_args(), Some("unknown"))
+            .is_err());
+
+        let config = Config::default();
+        assert!(config.connection_settings(&database_args(), None).is_err());
+    }
+
+    #[test]
+    fn should_use_socket_and_tls_settings() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let actual = config
+            .connection_settings(&database_args(), Some("local"))
+            .unwrap();
+
+        assert_eq!(actual.socket, Some("/run/mysqld/mysqld.sock".to_string()));
+        assert_eq!(
+            actual.tls,
+            Some(TlsSettings {
+                ca_file: Some(PathBuf::from("/etc/ssl/ca.pem")),
+                client_identity: None,
+                client_identity_password: None,
+                verify_hostname: false,
+            })
+        );
+
+        let actual = config
+            .connection_settings(&database_args(), Some("prod"))
+            .unwrap();
+
+        assert_eq!(actual.socket, None);
+        assert_eq!(actual.tls, None);
+
+        let args = DatabaseArgs {
+            tls: true,
+            ..database_args()
+        };
+
+        let actual = config.connection_settings(&args, Some("prod")).unwrap();
+
+        assert_eq!(
+            actual.tls,
+            Some(TlsSettings {
+                ca_file: None,
+                client_identity: None,
+                client_identity_password: None,
+                verify_hostname: true,
+            })
+        );
+    }
+
+    #[test]
+    fn should_merge_filter() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let actual = config.filter(&FilterArgs {
+            ignore_exports_since: None,
+            ignore_non_obds_2: false,
+            no_ignore_non_obds_2: false,
+            include_extern: false,
+            no_include_extern: false,
+            include_histo_zyto: true,
+            no_include_histo_zyto: false,
+        });
+
+        assert_eq!(actual.ignore_exports_since, Some("2024-01-01".to_string()));
+        assert!(!actual.ignore_non_obds_2);
+        assert!(actual.include_extern);
+        assert!(actual.include_histo_zyto);
+    }
+
+    #[test]
+    fn should_disable_filter_flag_of_config_with_args() {
+        let config =
+            Config::parse("[filter]\nignore_non_obds_2 = true\ninclude_extern = true").unwrap();
+
+        let actual = config.filter(&FilterArgs {
+            ignore_exports_since: None,
+            ignore_non_obds_2: false,
+            no_ignore_non_obds_2: false,
+            include_extern: false,
+            no_include_extern: true,
+            include_histo_zyto: false,
+            no_include_histo_zyto: false,
+        });
+
+        assert!(actual.ignore_non_obds_2);
+        assert!(!actual.include_extern);
+        assert!(!actual.include_histo_zyto);
+    }
+
+    #[test]
+    fn should_reject_invalid_date() {
+        assert!(Config::parse("[filter]\nignore_exports_since = \"01.01.2024\"").is_err());
+    }
+
+    #[test]
+    fn should_reject_invalid_entry_rules() {
+        assert!(Config::parse(CONFIG).unwrap().entry_rules().is_ok());
+        assert!(Config::parse("[extern]\nabsender = [\"[\"]").is_err());
+        assert!(Config::parse("[condition_id]\npattern = \"{patient_id}\"").is_err());
+    }
+}
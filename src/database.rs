@@ -18,149 +18,263 @@
  * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
  */
 
+use std::error::Error;
+use std::fmt::{Display, Formatter};
 use std::time::Duration;
 
 use mysql::prelude::Queryable;
-use mysql::{params, Pool};
+use mysql::{params, ClientIdentity, Conn, DriverError, OptsBuilder, SslOpts};
 
-use crate::common::{ExportData, Icd10GroupSize};
-use crate::resources::{EXPORTED_TO_LKR, EXPORT_QUERY, SQL_QUERY, SQL_QUERY_WITH_SCHEMA_VERSION};
+use crate::common::MeldungExport;
+use crate::config::ConnectionSettings;
+use crate::filter::{EntryRules, MeldungEntry, PatientFilter};
+use crate::resources::{EXPORTED_TO_LKR, MELDUNG_EXPORT};
 
-fn result_mapper() -> fn((String, String, usize)) -> Icd10GroupSize {
-    |(icd10_group, _, count)| Icd10GroupSize {
-        name: icd10_group,
-        schema_version: None,
-        size: count,
-    }
-}
+/// Statement to start a transaction all queries of one run are executed in.
+/// A consistent snapshot requires isolation level `REPEATABLE READ`.
+const START_TRANSACTION: &str = "START TRANSACTION READ ONLY, WITH CONSISTENT SNAPSHOT";
 
-fn result_mapper_with_schema_version() -> fn((String, String, usize)) -> Icd10GroupSize {
-    |(icd10_group, schema_version, count)| Icd10GroupSize {
-        name: icd10_group,
-        schema_version: Some(schema_version),
-        size: count,
-    }
+/// Database connection used for all queries of one command. All queries see the same
+/// database state even if Onkostar keeps writing data in the meantime.
+pub struct DatabaseSource {
+    connection: Conn,
 }
 
-pub struct DatabaseSource(String);
-
 impl DatabaseSource {
-    pub fn new(database: &str, host: &str, password: &str, port: u16, user: &str) -> Self {
-        let password = urlencoding::encode(password);
-        let url = format!("mysql://{user}:{password}@{host}:{port}/{database}");
-        DatabaseSource(url)
+    pub fn connect(settings: &ConnectionSettings, password: &str) -> Result<Self, DatabaseError> {
+        let ssl_opts = settings.tls.as_ref().map(|tls| {
+            SslOpts::default()
+                .with_root_cert_path(tls.ca_file.clone())
+                .with_client_identity(tls.client_identity.as_ref().map(|client_identity| {
+                    let identity = ClientIdentity::new(client_identity.clone());
+                    match tls.client_identity_password {
+                        Some(ref password) => identity.with_password(password.to_string()),
+                        None => identity,
+                    }
+                }))
+                .with_danger_skip_domain_validation(!tls.verify_hostname)
+        });
+
+        let opts = OptsBuilder::new()
+            .ip_or_hostname(Some(settings.host.as_str()))
+            .tcp_port(settings.port)
+            .socket(settings.socket.as_deref())
+            .user(Some(settings.user.as_str()))
+            .pass(Some(password))
+            .db_name(Some(settings.database.as_str()))
+            .ssl_opts(ssl_opts)
+            .tcp_connect_timeout(Some(Duration::from_secs(3)));
+
+        let mut connection = Conn::new(opts).map_err(DatabaseError::from_connection_error)?;
+
+        connection
+            .query_drop("SET SESSION TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+            .map_err(DatabaseError::from_query_error)?;
+        connection
+            .query_drop(START_TRANSACTION)
+            .map_err(DatabaseError::from_query_error)?;
+
+        Ok(DatabaseSource { connection })
     }
 
-    pub fn check(
-        &self,
+    /// Entries of `lkr_meldung_export` with given diagnosis year
+    pub fn entries(
+        &mut self,
         year: &str,
-        ignore_exports_since: &str,
-        ignore_non_obds_2: bool,
-        include_extern: bool,
-        include_histo_zyto: bool,
-        schema_versions: bool,
-    ) -> Result<Vec<Icd10GroupSize>, ()> {
-        let params = params! {
-            "year" => year,
-            "ignore_exports_since" => ignore_exports_since,
-            "ignore_non_obds_2" => if ignore_non_obds_2 { 1 } else { 0 },
-            "include_extern" => if include_extern { 1 } else { 0 },
-            "include_histo_zyto" => if include_histo_zyto { 1 } else { 0 }
-        };
-
-        match Pool::new(self.0.as_str()) {
-            Ok(pool) => {
-                if let Ok(mut connection) = pool.try_get_conn(Duration::from_secs(3)) {
-                    return match schema_versions {
-                        true => match connection.exec_map(
-                            SQL_QUERY_WITH_SCHEMA_VERSION,
-                            params,
-                            result_mapper_with_schema_version(),
-                        ) {
-                            Ok(result) => Ok(result),
-                            Err(_) => Err(()),
-                        },
-                        false => match connection.exec_map(SQL_QUERY, params, result_mapper()) {
-                            Ok(result) => Ok(result),
-                            Err(_) => Err(()),
-                        },
-                    };
-                }
+        rules: &EntryRules,
+    ) -> Result<Vec<MeldungEntry>, DatabaseError> {
+        let mut entries = vec![];
+        for row in self.rows(year)? {
+            let entry = MeldungEntry::new(&row?, rules);
+            if entry.diagnosis_year == year {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Entries of `lkr_meldung_export` of given condition and patient in any diagnosis year.
+    /// Only rows containing the patient ID are read.
+    pub fn condition_entries(
+        &mut self,
+        condition_id: &str,
+        patient_id: &str,
+        rules: &EntryRules,
+    ) -> Result<Vec<MeldungEntry>, DatabaseError> {
+        let patient_filter = PatientFilter::new(patient_id);
+        let mut entries = vec![];
+        for row in self.rows(patient_filter.containing())? {
+            let row = row?;
+            if !patient_filter.is_match(&row.xml_daten) {
+                continue;
             }
-            Err(_) => {
-                return Err(());
+            let entry = MeldungEntry::new(&row, rules);
+            if entry.condition_id == condition_id && entry.patient_id == patient_id {
+                entries.push(entry);
             }
         }
+        Ok(entries)
+    }
 
-        Err(())
+    pub fn exported(&mut self, package: u16) -> Result<Vec<(String, String)>, DatabaseError> {
+        self.connection
+            .exec_map(
+                EXPORTED_TO_LKR,
+                params! {
+                    "export_id" => package,
+                },
+                |(id, xml_data)| (id, xml_data),
+            )
+            .map_err(DatabaseError::from_query_error)
     }
 
-    pub fn export(
-        &self,
-        year: &str,
-        ignore_exports_since: &str,
-        ignore_non_obds_2: bool,
-        use_pat_id: bool,
-        include_extern: bool,
-        include_histo_zyto: bool,
-    ) -> Result<Vec<ExportData>, ()> {
-        match Pool::new(self.0.as_str()) {
-            Ok(pool) => {
-                if let Ok(mut connection) = pool.try_get_conn(Duration::from_secs(3)) {
-                    return match connection.exec_map(
-                        EXPORT_QUERY,
-                        params! {
-                            "year" => year,
-                            "ignore_exports_since" => ignore_exports_since,
-                            "ignore_non_obds_2" => if ignore_non_obds_2 { 1 } else { 0 },
-                            "include_extern" => if include_extern { 1 } else { 0 },
-                            "include_histo_zyto" => if include_histo_zyto { 1 } else { 0 }
-                        },
-                        |(condition_id, icd_10_code, diagnosis_date, pat_id)| ExportData {
-                            condition_id,
-                            icd_10_code,
-                            diagnosis_date,
-                            pat_id: if use_pat_id { Some(pat_id) } else { None },
-                        },
-                    ) {
-                        Ok(result) => Ok(result),
-                        Err(_) => {
-                            return Err(());
+    /// Streams all rows of `lkr_meldung_export` as used for a snapshot. This is the only
+    /// query reading the whole table.
+    pub fn meldung_exports(
+        &mut self,
+    ) -> Result<impl Iterator<Item = Result<MeldungExport, DatabaseError>> + '_, DatabaseError>
+    {
+        self.rows("")
+    }
+
+    /// Streams rows of `lkr_meldung_export` containing the given text in `xml_daten`.
+    /// This is a cheap preselection, exact rules are applied in module `filter`.
+    fn rows(
+        &mut self,
+        containing: &str,
+    ) -> Result<impl Iterator<Item = Result<MeldungExport, DatabaseError>> + '_, DatabaseError>
+    {
+        let result = self
+            .connection
+            .exec_iter(
+                MELDUNG_EXPORT,
+                params! {
+                    "containing" => containing,
+                },
+            )
+            .map_err(DatabaseError::from_query_error)?;
+
+        Ok(result.map(|row| {
+            row.and_then(|row| mysql::from_row_opt(row).map_err(mysql::Error::from))
+                .map(
+                    |(
+                        id,
+                        lkr_meldung,
+                        lkr_export,
+                        typ,
+                        versionsnummer,
+                        xml_daten,
+                        exportiert_am,
+                    )| {
+                        MeldungExport {
+                            id,
+                            lkr_meldung,
+                            lkr_export,
+                            typ,
+                            versionsnummer,
+                            xml_daten,
+                            exportiert_am,
                         }
-                    };
-                }
+                    },
+                )
+                .map_err(DatabaseError::from_query_error)
+        }))
+    }
+}
+
+impl Drop for DatabaseSource {
+    fn drop(&mut self) {
+        // Read only transaction, nothing to commit
+        let _ = self.connection.query_drop("ROLLBACK");
+    }
+}
+
+/// MySQL/MariaDB error code for denied access using given user and password
+const ER_ACCESS_DENIED_ERROR: u16 = 1045;
+/// MySQL/MariaDB error code for denied access to database
+const ER_DBACCESS_DENIED_ERROR: u16 = 1044;
+/// MySQL/MariaDB error code for unknown database
+const ER_BAD_DB_ERROR: u16 = 1049;
+/// MySQL/MariaDB error code for missing table
+const ER_NO_SUCH_TABLE: u16 = 1146;
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// Authentication failed using given user and password
+    Authentication(mysql::Error),
+    /// Database does not exist or access is denied
+    UnknownDatabase(mysql::Error),
+    /// Database server not reachable or TLS handshake failed
+    Connection(mysql::Error),
+    /// Database server did not accept the connection within timeout
+    Timeout,
+    /// Required table does not exist
+    MissingTable(mysql::Error),
+    /// Any other error while executing a query
+    Query(mysql::Error),
+}
+
+impl DatabaseError {
+    fn from_connection_error(err: mysql::Error) -> Self {
+        match err {
+            mysql::Error::MySqlError(ref mysql_err) if mysql_err.code == ER_ACCESS_DENIED_ERROR => {
+                DatabaseError::Authentication(err)
             }
-            Err(_) => {
-                return Err(());
+            mysql::Error::MySqlError(ref mysql_err)
+                if mysql_err.code == ER_BAD_DB_ERROR
+                    || mysql_err.code == ER_DBACCESS_DENIED_ERROR =>
+            {
+                DatabaseError::UnknownDatabase(err)
             }
+            mysql::Error::DriverError(DriverError::Timeout)
+            | mysql::Error::DriverError(DriverError::ConnectTimeout) => DatabaseError::Timeout,
+            err => DatabaseError::Connection(err),
         }
+    }
 
-        Err(())
+    fn from_query_error(err: mysql::Error) -> Self {
+        match err {
+            mysql::Error::MySqlError(ref mysql_err) if mysql_err.code == ER_NO_SUCH_TABLE => {
+                DatabaseError::MissingTable(err)
+            }
+            err => DatabaseError::Query(err),
+        }
     }
+}
 
-    pub fn exported(&self, package: u16) -> Result<Vec<(String, String)>, ()> {
-        match Pool::new(self.0.as_str()) {
-            Ok(pool) => {
-                if let Ok(mut connection) = pool.try_get_conn(Duration::from_secs(3)) {
-                    return match connection.exec_map(
-                        EXPORTED_TO_LKR,
-                        params! {
-                            "export_id" => package,
-                        },
-                        |(id, xml_data)| (id, xml_data),
-                    ) {
-                        Ok(result) => Ok(result),
-                        Err(_) => {
-                            return Err(());
-                        }
-                    };
-                }
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatabaseError::Authentication(_) => write!(
+                f,
+                "Anmeldung an der Datenbank fehlgeschlagen. Benutzername oder Passwort prüfen"
+            ),
+            DatabaseError::UnknownDatabase(err) => {
+                write!(f, "Datenbank nicht vorhanden oder kein Zugriff: {}", err)
+            }
+            DatabaseError::Connection(err) => {
+                write!(f, "Datenbank-Server nicht erreichbar: {}", err)
             }
-            Err(_) => {
-                return Err(());
+            DatabaseError::Timeout => {
+                write!(f, "Zeitüberschreitung beim Verbindungsaufbau zur Datenbank")
             }
+            DatabaseError::MissingTable(err) => {
+                write!(f, "Benötigte Tabelle nicht vorhanden: {}", err)
+            }
+            DatabaseError::Query(err) => write!(f, "Fehler bei Datenbankabfrage: {}", err),
         }
+    }
+}
 
-        Err(())
+impl Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DatabaseError::Authentication(err)
+            | DatabaseError::UnknownDatabase(err)
+            | DatabaseError::Connection(err)
+            | DatabaseError::MissingTable(err)
+            | DatabaseError::Query(err) => Some(err),
+            DatabaseError::Timeout => None,
+        }
     }
 }
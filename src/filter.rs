@@ -0,0 +1,1019 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+//! Rules to select conditions from rows of `lkr_meldung_export`. These rules are
+//! used for database and snapshot sources to get identical results.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use itertools::Itertools;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::common::{Check, ExportData, Icd10GroupSize, MeldungExport};
+use crate::icd_groups::IcdGroups;
+use crate::lkrexport::{ConditionIdScheme, Meldung};
+
+/// Values of one row of `lkr_meldung_export` used to select conditions
+pub struct MeldungEntry {
+    pub id: u64,
+    pub lkr_meldung: Option<u64>,
+    pub meldung_id: Option<String>,
+    pub typ: Option<i64>,
+    pub versionsnummer: Option<u64>,
+    pub exportiert_am: Option<NaiveDateTime>,
+    pub patient_id: String,
+    pub schema_version: String,
+    pub condition_id: String,
+    pub icd10_code: String,
+    pub diagnosis_date: String,
+    pub diagnosis_year: String,
+    pub diagnosis_period: Option<(NaiveDate, NaiveDate)>,
+    pub meldedatum: Option<NaiveDate>,
+    pub has_icd_version: bool,
+    pub has_tumorkonferenz: bool,
+    pub is_histo_zyto: bool,
+    pub meldende_stelle: String,
+    pub is_extern: bool,
+}
+
+/// Rules to detect Meldungen with external diagnosis. A Meldung is external, if any rule matches.
+pub struct ExternRule {
+    meldende_stelle: Vec<Regex>,
+    melder_ids: Vec<String>,
+    absender: Vec<Regex>,
+}
+
+impl Default for ExternRule {
+    /// `Meldende_Stelle` contains `9999`, as used by the former SQL queries
+    fn default() -> Self {
+        ExternRule {
+            meldende_stelle: vec![Regex::new("9999").unwrap()],
+            melder_ids: vec![],
+            absender: vec![],
+        }
+    }
+}
+
+impl ExternRule {
+    /// Uses patterns for `Meldende_Stelle` and `Absender_ID` and exact values of `Melder_ID`
+    pub fn new(
+        meldende_stelle: &[String],
+        melder_ids: &[String],
+        absender: &[String],
+    ) -> Result<Self, String> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    Regex::new(pattern).map_err(|_| {
+                        format!("Ungültiges Muster für externe Meldungen '{}'", pattern)
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(ExternRule {
+            meldende_stelle: compile(meldende_stelle)?,
+            melder_ids: melder_ids.to_vec(),
+            absender: compile(absender)?,
+        })
+    }
+
+    fn is_extern(&self, meldung: &Meldung) -> bool {
+        let meldende_stelle = meldung.meldende_stelle();
+        let absender_id = meldung.absender_id();
+
+        self.meldende_stelle
+            .iter()
+            .any(|pattern| pattern.is_match(&meldende_stelle))
+            || self.melder_ids.contains(&meldung.melder_id())
+            || (!absender_id.is_empty()
+                && self
+                    .absender
+                    .iter()
+                    .any(|pattern| pattern.is_match(&absender_id)))
+    }
+}
+
+/// Site specific rules applied when reading entries
+#[derive(Default)]
+pub struct EntryRules {
+    pub extern_rule: ExternRule,
+    pub condition_id: ConditionIdScheme,
+}
+
+/// Preselects rows of one patient before parsing them. Matches the `Patient_ID` attribute
+/// with single or double quotes and whitespace around `=`.
+pub struct PatientFilter {
+    patient_id: String,
+    attribute: Regex,
+}
+
+impl PatientFilter {
+    pub fn new(patient_id: &str) -> Self {
+        PatientFilter {
+            patient_id: patient_id.to_string(),
+            attribute: Regex::new(&format!(
+                r#"Patient_ID\s*=\s*("{0}"|'{0}')"#,
+                regex::escape(patient_id)
+            ))
+            .unwrap(),
+        }
+    }
+
+    /// Text all rows of the patient contain, e.g. to be used with SQL `LIKE`
+    pub fn containing(&self) -> &str {
+        &self.patient_id
+    }
+
+    pub fn is_match(&self, xml: &str) -> bool {
+        self.attribute.is_match(xml)
+    }
+}
+
+impl MeldungEntry {
+    pub fn new(row: &MeldungExport, rules: &EntryRules) -> Self {
+        let meldung = Meldung::new(&row.xml_daten);
+
+        MeldungEntry {
+            id: row.id,
+            lkr_meldung: row.lkr_meldung,
+            meldung_id: meldung.id(),
+            typ: row.typ,
+            versionsnummer: row.versionsnummer,
+            exportiert_am: row.exportiert_am.as_ref().and_then(|exportiert_am| {
+                NaiveDateTime::parse_from_str(exportiert_am, "%Y-%m-%d %H:%M:%S").ok()
+            }),
+            patient_id: meldung.patient_id(),
+            schema_version: meldung.schema_version(),
+            condition_id: meldung.condition_id(&rules.condition_id),
+            icd10_code: meldung.primary_icd10_code(),
+            diagnosis_date: meldung.diagnosis_date(),
+            diagnosis_year: meldung.diagnosis_year(),
+            diagnosis_period: diagnosis_period(&meldung.diagnosis_date()),
+            meldedatum: meldung.meldedatum(),
+            has_icd_version: meldung.has_icd_version(),
+            has_tumorkonferenz: meldung.has_tumorkonferenz(),
+            is_histo_zyto: row.xml_daten.contains("histologie_zytologie"),
+            meldende_stelle: meldung.meldende_stelle(),
+            is_extern: rules.extern_rule.is_extern(&meldung),
+        }
+    }
+
+    fn is_selected(
+        &self,
+        ignore_non_obds_2: bool,
+        include_extern: bool,
+        include_histo_zyto: bool,
+    ) -> bool {
+        self.exclusion_reasons(ignore_non_obds_2, include_extern, include_histo_zyto)
+            .is_empty()
+    }
+
+    /// All selection rules not met by this entry, regardless of its version
+    fn exclusion_reasons(
+        &self,
+        ignore_non_obds_2: bool,
+        include_extern: bool,
+        include_histo_zyto: bool,
+    ) -> Vec<ExclusionReason> {
+        [
+            (!self.has_icd_version, ExclusionReason::MissingIcdVersion),
+            (
+                self.typ.is_none_or(|typ| typ == -1),
+                ExclusionReason::Deleted,
+            ),
+            (self.has_tumorkonferenz, ExclusionReason::Tumorkonferenz),
+            (
+                self.is_histo_zyto && !include_histo_zyto,
+                ExclusionReason::HistoZyto,
+            ),
+            (self.is_extern && !include_extern, ExclusionReason::Extern),
+            (
+                !self.schema_version.starts_with("2.") && !ignore_non_obds_2,
+                ExclusionReason::SchemaVersion,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(matches, reason)| matches.then_some(reason))
+        .collect()
+    }
+}
+
+/// Selection rule a Meldung was excluded by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExclusionReason {
+    /// Diagnosis date not within the requested period
+    DiagnosisPeriod,
+    /// No `ICD_Version` given
+    MissingIcdVersion,
+    /// Column `typ` is `-1` or missing
+    Deleted,
+    /// Contains `Menge_Tumorkonferenz`
+    Tumorkonferenz,
+    /// Reason `histologie_zytologie` without `--include-histo-zyto`
+    HistoZyto,
+    /// External Meldung without `--include-extern`
+    Extern,
+    /// Schema version is not oBDS 2.x
+    SchemaVersion,
+    /// A newer version of the condition exists
+    OutdatedVersion,
+}
+
+impl Display for ExclusionReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExclusionReason::DiagnosisPeriod => write!(f, "Diagnosedatum außerhalb des Zeitraums"),
+            ExclusionReason::MissingIcdVersion => write!(f, "Keine Angabe 'ICD_Version'"),
+            ExclusionReason::Deleted => write!(f, "Typ -1 oder nicht angegeben"),
+            ExclusionReason::Tumorkonferenz => write!(f, "Meldung zu einer Tumorkonferenz"),
+            ExclusionReason::HistoZyto => {
+                write!(
+                    f,
+                    "Meldeanlass 'histologie_zytologie' (--include-histo-zyto)"
+                )
+            }
+            ExclusionReason::Extern => write!(f, "Externe Meldung (--include-extern)"),
+            ExclusionReason::SchemaVersion => write!(f, "Kein oBDS 2.x"),
+            ExclusionReason::OutdatedVersion => {
+                write!(
+                    f,
+                    "Neuere Version vorhanden (ggf. vor --ignore-exports-since)"
+                )
+            }
+        }
+    }
+}
+
+/// One entry and all selection rules it does not meet. Entries without reasons are selected.
+#[derive(Serialize)]
+pub struct Exclusion {
+    pub id: u64,
+    pub lkr_meldung: Option<u64>,
+    pub meldung_id: Option<String>,
+    pub versionsnummer: Option<u64>,
+    pub condition_id: String,
+    pub icd10_code: String,
+    pub diagnosis_date: String,
+    pub reasons: Vec<ExclusionReason>,
+}
+
+impl Exclusion {
+    fn new(
+        entry: &MeldungEntry,
+        period: Option<&DiagnosisPeriod>,
+        max_versions: &HashMap<&str, u64>,
+        ignore_non_obds_2: bool,
+        include_extern: bool,
+        include_histo_zyto: bool,
+    ) -> Self {
+        let mut reasons = vec![];
+        if period.is_some_and(|period| !period.contains(entry)) {
+            reasons.push(ExclusionReason::DiagnosisPeriod);
+        }
+        reasons.extend(entry.exclusion_reasons(
+            ignore_non_obds_2,
+            include_extern,
+            include_histo_zyto,
+        ));
+        if is_outdated(entry, max_versions) {
+            reasons.push(ExclusionReason::OutdatedVersion);
+        }
+
+        Exclusion {
+            id: entry.id,
+            lkr_meldung: entry.lkr_meldung,
+            meldung_id: entry.meldung_id.clone(),
+            versionsnummer: entry.versionsnummer,
+            condition_id: entry.condition_id.to_string(),
+            icd10_code: entry.icd10_code.to_string(),
+            diagnosis_date: entry.diagnosis_date.to_string(),
+            reasons,
+        }
+    }
+
+    pub fn is_selected(&self) -> bool {
+        self.reasons.is_empty()
+    }
+}
+
+/// Diagnoses to select conditions for
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DiagnosisPeriod {
+    /// All diagnoses with given year in `Diagnosedatum`
+    Year(String),
+    /// Diagnoses within given dates, both included
+    Range(NaiveDate, NaiveDate),
+}
+
+impl DiagnosisPeriod {
+    /// Diagnosis years to fetch entries for
+    pub fn years(&self) -> Vec<String> {
+        match self {
+            DiagnosisPeriod::Year(year) => vec![year.to_string()],
+            DiagnosisPeriod::Range(from, to) => (from.year()..=to.year())
+                .map(|year| year.to_string())
+                .collect(),
+        }
+    }
+
+    /// Diagnosis year used to select the version of ICD-10 groups
+    pub fn grouping_year(&self) -> String {
+        match self {
+            DiagnosisPeriod::Year(year) => year.to_string(),
+            DiagnosisPeriod::Range(_, to) => to.year().to_string(),
+        }
+    }
+
+    /// Short name usable as file name, e.g. `2024` or `2024-01-01_2024-06-30`
+    pub fn label(&self) -> String {
+        match self {
+            DiagnosisPeriod::Year(year) => year.to_string(),
+            DiagnosisPeriod::Range(from, to) => format!("{}_{}", from, to),
+        }
+    }
+
+    pub fn contains(&self, entry: &MeldungEntry) -> bool {
+        match self {
+            DiagnosisPeriod::Year(year) => &entry.diagnosis_year == year,
+            DiagnosisPeriod::Range(..) => entry
+                .diagnosis_period
+                .is_some_and(|period| self.contains_period(period)),
+        }
+    }
+
+    /// Checks a diagnosis date in oBDS or ISO format. Returns `None` for unknown formats.
+    pub fn contains_date(&self, date: &str) -> Option<bool> {
+        diagnosis_period(date).map(|period| self.contains_period(period))
+    }
+
+    /// A partial date is contained only if the whole month or year is within this period
+    fn contains_period(&self, (first, last): (NaiveDate, NaiveDate)) -> bool {
+        match self {
+            DiagnosisPeriod::Year(year) => last.year().to_string() == *year,
+            DiagnosisPeriod::Range(from, to) => from <= &first && &last <= to,
+        }
+    }
+}
+
+impl Display for DiagnosisPeriod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosisPeriod::Year(year) => write!(f, "das Diagnosejahr {}", year),
+            DiagnosisPeriod::Range(from, to) => write!(
+                f,
+                "den Diagnosezeitraum {} bis {}",
+                from.format("%d.%m.%Y"),
+                to.format("%d.%m.%Y")
+            ),
+        }
+    }
+}
+
+/// First and last possible day of a diagnosis date like `10.06.2024`. oBDS uses `00` for
+/// unknown day or month, e.g. `00.05.2023` covers all days of May 2023.
+/// Dates in ISO format like `2024-06-10` or `2024-06` as used in CSV files for OPAL are accepted,
+/// too. The time of ISO date times is ignored.
+pub fn diagnosis_period(date: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let date = date.split([' ', 'T']).next().unwrap_or_default();
+    let parts = |separator| {
+        date.split(separator)
+            .map(|part| part.parse::<u32>().ok())
+            .collect::<Option<Vec<_>>>()
+    };
+
+    let (day, month, year) = match date.contains('-') {
+        true => match parts('-')?[..] {
+            [year, month, day] => (day, month, year),
+            [year, month] => (0, month, year),
+            _ => return None,
+        },
+        false => match parts('.')?[..] {
+            [day, month, year] => (day, month, year),
+            _ => return None,
+        },
+    };
+    let year = i32::try_from(year).ok()?;
+
+    match (day, month) {
+        (0, 0) => Some((
+            NaiveDate::from_ymd_opt(year, 1, 1)?,
+            NaiveDate::from_ymd_opt(year, 12, 31)?,
+        )),
+        (0, month) => {
+            let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+            let last = match month {
+                12 => NaiveDate::from_ymd_opt(year, 12, 31)?,
+                month => NaiveDate::from_ymd_opt(year, month + 1, 1)?.pred_opt()?,
+            };
+            Some((first, last))
+        }
+        (day, month) => {
+            let date = NaiveDate::from_ymd_opt(year, month, day)?;
+            Some((date, date))
+        }
+    }
+}
+
+/// Sums up distinct conditions by ICD-10 group and optionally by schema version.
+/// Entries must include all diagnosis years of the given period.
+#[allow(clippy::too_many_arguments)]
+pub fn group_sizes(
+    entries: &[&MeldungEntry],
+    period: &DiagnosisPeriod,
+    ignore_exports_since: &str,
+    ignore_non_obds_2: bool,
+    include_extern: bool,
+    include_histo_zyto: bool,
+    schema_versions: bool,
+    icd_groups: &IcdGroups,
+) -> Vec<Icd10GroupSize> {
+    counted(
+        entries,
+        period,
+        ignore_exports_since,
+        ignore_non_obds_2,
+        include_extern,
+        include_histo_zyto,
+    )
+    .into_iter()
+    .map(|entry| {
+        (
+            Check::map_icd_code(&entry.icd10_code, icd_groups),
+            match schema_versions {
+                true => Some(entry.schema_version.to_string()),
+                false => None,
+            },
+        )
+    })
+    .sorted()
+    .chunk_by(|group| group.clone())
+    .into_iter()
+    .map(|((name, schema_version), group)| Icd10GroupSize {
+        name,
+        schema_version,
+        size: group.count(),
+    })
+    .collect()
+}
+
+/// Number of conditions counted by `group_sizes()` reported by one `Meldende_Stelle`
+#[derive(Serialize)]
+pub struct MeldendeStelleSize {
+    pub name: String,
+    pub is_extern: bool,
+    pub size: usize,
+}
+
+/// Sums up the conditions counted by `group_sizes()` by `Meldende_Stelle`
+pub fn meldende_stellen(
+    entries: &[&MeldungEntry],
+    period: &DiagnosisPeriod,
+    ignore_exports_since: &str,
+    ignore_non_obds_2: bool,
+    include_extern: bool,
+    include_histo_zyto: bool,
+) -> Vec<MeldendeStelleSize> {
+    counted(
+        entries,
+        period,
+        ignore_exports_since,
+        ignore_non_obds_2,
+        include_extern,
+        include_histo_zyto,
+    )
+    .into_iter()
+    .map(|entry| (entry.meldende_stelle.as_str(), entry.is_extern))
+    .counts()
+    .into_iter()
+    .map(|((name, is_extern), size)| MeldendeStelleSize {
+        name: name.to_string(),
+        is_extern,
+        size,
+    })
+    .sorted_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)))
+    .collect()
+}
+
+/// Distinct entries counted by `group_sizes()`
+fn counted<'a>(
+    entries: &[&'a MeldungEntry],
+    period: &DiagnosisPeriod,
+    ignore_exports_since: &str,
+    ignore_non_obds_2: bool,
+    include_extern: bool,
+    include_histo_zyto: bool,
+) -> Vec<&'a MeldungEntry> {
+    let max_versions = max_versions_by_meldedatum(entries, ignore_exports_since);
+
+    entries
+        .iter()
+        .copied()
+        .filter(|entry| period.contains(entry))
+        .filter(|entry| entry.is_selected(ignore_non_obds_2, include_extern, include_histo_zyto))
+        .filter(|entry| !is_outdated(entry, &max_versions))
+        .unique_by(|entry| {
+            (
+                entry.lkr_meldung,
+                &entry.patient_id,
+                &entry.schema_version,
+                entry.versionsnummer,
+                &entry.condition_id,
+                &entry.icd10_code,
+            )
+        })
+        .collect()
+}
+
+/// Lists distinct conditions. Entries must include all diagnosis years of the given period.
+pub fn conditions(
+    entries: &[&MeldungEntry],
+    period: &DiagnosisPeriod,
+    ignore_exports_since: &str,
+    ignore_non_obds_2: bool,
+    use_pat_id: bool,
+    include_extern: bool,
+    include_histo_zyto: bool,
+) -> Vec<ExportData> {
+    let max_versions = max_versions_by_export_date(entries, ignore_exports_since);
+
+    entries
+        .iter()
+        .filter(|entry| period.contains(entry))
+        .filter(|entry| entry.is_selected(ignore_non_obds_2, include_extern, include_histo_zyto))
+        .filter(|entry| !is_outdated(entry, &max_versions))
+        .unique_by(|entry| {
+            (
+                &entry.patient_id,
+                entry.versionsnummer,
+                &entry.condition_id,
+                &entry.icd10_code,
+                &entry.diagnosis_date,
+            )
+        })
+        .map(|entry| ExportData {
+            condition_id: entry.condition_id.to_string(),
+            icd_10_code: entry.icd10_code.to_string(),
+            diagnosis_date: entry.diagnosis_date.to_string(),
+            pat_id: if use_pat_id {
+                Some(entry.patient_id.to_string())
+            } else {
+                None
+            },
+        })
+        .collect()
+}
+
+/// Lists distinct conditions of Meldungen of LKR protocol files. Protocol files contain no
+/// versions, the latest selected Meldung of a condition with ICD-10 code determines code and
+/// diagnosis date.
+pub fn protocol_conditions(
+    entries: &[MeldungEntry],
+    period: &DiagnosisPeriod,
+    ignore_non_obds_2: bool,
+    use_pat_id: bool,
+    include_extern: bool,
+    include_histo_zyto: bool,
+) -> Vec<ExportData> {
+    entries
+        .iter()
+        .filter(|entry| period.contains(entry))
+        .filter(|entry| entry.is_selected(ignore_non_obds_2, include_extern, include_histo_zyto))
+        .into_group_map_by(|entry| entry.condition_id.as_str())
+        .into_values()
+        .filter_map(|entries| {
+            entries
+                .into_iter()
+                .max_by_key(|entry| (!entry.icd10_code.is_empty(), entry.meldedatum))
+        })
+        .map(|entry| ExportData {
+            condition_id: entry.condition_id.to_string(),
+            icd_10_code: entry.icd10_code.to_string(),
+            diagnosis_date: entry.diagnosis_date.to_string(),
+            pat_id: use_pat_id.then(|| entry.patient_id.to_string()),
+        })
+        .sorted_by(|a, b| a.condition_id.cmp(&b.condition_id))
+        .collect()
+}
+
+/// Applies all rules of `group_sizes()` to each entry, optionally limited to the given period
+pub fn group_size_exclusions(
+    entries: &[&MeldungEntry],
+    period: Option<&DiagnosisPeriod>,
+    ignore_exports_since: &str,
+    ignore_non_obds_2: bool,
+    include_extern: bool,
+    include_histo_zyto: bool,
+) -> Vec<Exclusion> {
+    let max_versions = max_versions_by_meldedatum(entries, ignore_exports_since);
+    entries
+        .iter()
+        .map(|entry| {
+            Exclusion::new(
+                entry,
+                period,
+                &max_versions,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            )
+        })
+        .collect()
+}
+
+/// Applies all rules of `conditions()` to each entry, optionally limited to the given period
+pub fn condition_exclusions(
+    entries: &[&MeldungEntry],
+    period: Option<&DiagnosisPeriod>,
+    ignore_exports_since: &str,
+    ignore_non_obds_2: bool,
+    include_extern: bool,
+    include_histo_zyto: bool,
+) -> Vec<Exclusion> {
+    let max_versions = max_versions_by_export_date(entries, ignore_exports_since);
+    entries
+        .iter()
+        .map(|entry| {
+            Exclusion::new(
+                entry,
+                period,
+                &max_versions,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            )
+        })
+        .collect()
+}
+
+/// Versions as used for group sizes, based on `Meldedatum` like the former SQL query of `database`
+fn max_versions_by_meldedatum<'a>(
+    entries: &[&'a MeldungEntry],
+    ignore_exports_since: &str,
+) -> HashMap<&'a str, u64> {
+    let ignore_exports_since = parse_date(ignore_exports_since);
+    max_versions(entries.iter().copied(), |entry| {
+        entry
+            .meldedatum
+            .is_some_and(|meldedatum| meldedatum < ignore_exports_since)
+    })
+}
+
+/// Versions as used for conditions, based on the export date like the former SQL query of `export`
+fn max_versions_by_export_date<'a>(
+    entries: &[&'a MeldungEntry],
+    ignore_exports_since: &str,
+) -> HashMap<&'a str, u64> {
+    let ignore_exports_since = parse_date(ignore_exports_since).and_hms_opt(0, 0, 0);
+    max_versions(
+        // Only entries joined with `lkr_export`
+        entries
+            .iter()
+            .copied()
+            .filter(|entry| entry.exportiert_am.is_some()),
+        |entry| entry.exportiert_am < ignore_exports_since,
+    )
+}
+
+/// Highest version of each condition. The date of the first entry of a condition decides,
+/// whether all versions are ignored, as it did in the former SQL queries.
+fn max_versions<'a>(
+    entries: impl Iterator<Item = &'a MeldungEntry>,
+    before_ignored_exports: impl Fn(&MeldungEntry) -> bool,
+) -> HashMap<&'a str, u64> {
+    entries
+        .sorted_by_key(|entry| entry.id)
+        .into_group_map_by(|entry| entry.condition_id.as_str())
+        .into_iter()
+        .filter_map(
+            |(condition_id, group)| match before_ignored_exports(group[0]) {
+                true => group
+                    .iter()
+                    .filter_map(|entry| entry.versionsnummer)
+                    .max()
+                    .map(|max_version| (condition_id, max_version)),
+                false => Some((condition_id, u64::MAX)),
+            },
+        )
+        .collect()
+}
+
+fn is_outdated(entry: &MeldungEntry, max_versions: &HashMap<&str, u64>) -> bool {
+    match (
+        entry.versionsnummer,
+        max_versions.get(entry.condition_id.as_str()),
+    ) {
+        (Some(version), Some(&max_version)) => version < max_version,
+        _ => false,
+    }
+}
+
+fn parse_date(value: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap_or(NaiveDate::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::common::MeldungExport;
+    use crate::filter::{
+        condition_exclusions, conditions, diagnosis_period, group_sizes, meldende_stellen,
+        DiagnosisPeriod, EntryRules, ExclusionReason, ExternRule, MeldungEntry, PatientFilter,
+    };
+    use crate::icd_groups::IcdGroups;
+
+    fn entry(row: &MeldungExport) -> MeldungEntry {
+        MeldungEntry::new(row, &EntryRules::default())
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn row(id: u64, versionsnummer: u64, icd10_code: &str, meldende_stelle: &str) -> MeldungExport {
+        MeldungExport {
+            id,
+            lkr_meldung: Some(id),
+            lkr_export: Some(1),
+            typ: Some(0),
+            versionsnummer: Some(versionsnummer),
+            xml_daten: format!(
+                r#"<ADT_GEKID Schema_Version="2.2.3">
+                    <Patienten_Stammdaten Patient_ID="20001234"></Patienten_Stammdaten>
+                    <Meldende_Stelle>{}</Meldende_Stelle>
+                    <Meldung Meldung_ID="TEST{}">
+                        <Meldedatum>11.06.2024</Meldedatum>
+                        <Diagnose Tumor_ID="1">
+                            <Primaertumor_ICD_Code>{}</Primaertumor_ICD_Code>
+                            <Primaertumor_ICD_Version>10 2015 GM</Primaertumor_ICD_Version>
+                            <Diagnosedatum>10.06.2024</Diagnosedatum>
+                        </Diagnose>
+                    </Meldung>
+                </ADT_GEKID>"#,
+                meldende_stelle, id, icd10_code
+            ),
+            exportiert_am: Some("2024-06-12 08:00:00".into()),
+        }
+    }
+
+    #[test]
+    fn should_extract_values_like_sql_queries() {
+        let actual = entry(&row(1, 1, "C17.1", "TEST"));
+
+        assert_eq!(actual.patient_id, "20001234");
+        assert_eq!(
+            actual.condition_id,
+            "d6ad167b59a87a3e92105964ee0b15a365c92397ec4b04cffecbb9a997754611"
+        );
+        assert_eq!(actual.schema_version, "2.2.3");
+        assert_eq!(actual.icd10_code, "C17.1");
+        assert_eq!(actual.diagnosis_date, "10.06.2024");
+        assert_eq!(actual.diagnosis_year, "2024");
+        assert!(actual.has_icd_version);
+        assert!(!actual.is_extern);
+        assert!(entry(&row(1, 1, "C17.1", "TEST9999")).is_extern);
+    }
+
+    #[test]
+    fn should_preselect_patient_regardless_of_quotes_and_whitespace() {
+        let patient_filter = PatientFilter::new("20001234");
+
+        assert!(patient_filter.is_match(r#"<Patienten_Stammdaten Patient_ID="20001234">"#));
+        assert!(patient_filter.is_match("<Patienten_Stammdaten Patient_ID = '20001234'>"));
+        assert!(!patient_filter.is_match(r#"<Patienten_Stammdaten Patient_ID="200012345">"#));
+        assert!(!patient_filter.is_match(r#"<Tumorzuordnung Tumor_ID="20001234">"#));
+    }
+
+    #[test]
+    fn should_check_elements_instead_of_raw_content() {
+        let mut prefixed = row(1, 1, "C17.1", "TEST");
+        prefixed.xml_daten = prefixed.xml_daten.replace(
+            "<Diagnose ",
+            "<adt:Menge_Tumorkonferenz xmlns:adt=\"urn:gekid\"/><Diagnose ",
+        );
+
+        let actual = entry(&prefixed);
+
+        assert!(actual.has_tumorkonferenz);
+        assert!(actual.has_icd_version);
+
+        let mut text_only = row(1, 1, "C17.1", "TEST");
+        text_only.xml_daten = text_only
+            .xml_daten
+            .replace(
+                "<Primaertumor_ICD_Version>10 2015 GM</Primaertumor_ICD_Version>",
+                "<!-- <Menge_Tumorkonferenz> --><Anmerkung>ICD_Version fehlt, &lt;Menge_Tumorkonferenz&gt;</Anmerkung>",
+            );
+
+        let actual = entry(&text_only);
+
+        assert!(!actual.has_tumorkonferenz);
+        assert!(!actual.has_icd_version);
+    }
+
+    #[test]
+    fn should_use_latest_version_only() {
+        let entries = [
+            row(1, 1, "C17.1", "TEST"),
+            row(2, 2, "C61", "TEST"),
+            row(3, 1, "C50.1", "TEST9999"),
+        ]
+        .iter()
+        .map(entry)
+        .collect::<Vec<_>>();
+        let entries = entries.iter().collect::<Vec<_>>();
+        let year = DiagnosisPeriod::Year("2024".into());
+
+        let actual = group_sizes(
+            &entries,
+            &year,
+            "9999-12-31",
+            false,
+            false,
+            false,
+            false,
+            &IcdGroups::default(),
+        );
+
+        assert_eq!(actual.len(), 1);
+        assert_eq!(actual[0].name, "C61");
+        assert_eq!(actual[0].size, 1);
+
+        let actual = conditions(&entries, &year, "9999-12-31", false, true, false, false);
+
+        assert_eq!(actual.len(), 1);
+        assert_eq!(actual[0].icd_10_code, "C61");
+        assert_eq!(actual[0].pat_id, Some("20001234".into()));
+    }
+
+    #[test]
+    fn should_ignore_conditions_exported_since_date() {
+        let entries = [row(1, 1, "C17.1", "TEST"), row(2, 2, "C61", "TEST")]
+            .iter()
+            .map(entry)
+            .collect::<Vec<_>>();
+        let entries = entries.iter().collect::<Vec<_>>();
+        let year = DiagnosisPeriod::Year("2024".into());
+
+        assert!(group_sizes(
+            &entries,
+            &year,
+            "2024-06-01",
+            false,
+            false,
+            false,
+            false,
+            &IcdGroups::default(),
+        )
+        .is_empty());
+        assert!(conditions(&entries, &year, "2024-06-01", false, false, false, false).is_empty());
+    }
+
+    #[test]
+    fn should_parse_partial_diagnosis_dates() {
+        assert_eq!(
+            diagnosis_period("10.06.2024"),
+            Some((date(2024, 6, 10), date(2024, 6, 10)))
+        );
+        assert_eq!(
+            diagnosis_period("00.02.2024"),
+            Some((date(2024, 2, 1), date(2024, 2, 29)))
+        );
+        assert_eq!(
+            diagnosis_period("00.12.2023"),
+            Some((date(2023, 12, 1), date(2023, 12, 31)))
+        );
+        assert_eq!(
+            diagnosis_period("00.00.2023"),
+            Some((date(2023, 1, 1), date(2023, 12, 31)))
+        );
+        assert_eq!(
+            diagnosis_period("2024-06-10 00:00:00"),
+            Some((date(2024, 6, 10), date(2024, 6, 10)))
+        );
+        assert_eq!(
+            diagnosis_period("2024-6-5"),
+            Some((date(2024, 6, 5), date(2024, 6, 5)))
+        );
+        assert_eq!(
+            diagnosis_period("2024-06"),
+            Some((date(2024, 6, 1), date(2024, 6, 30)))
+        );
+        assert_eq!(diagnosis_period("31.02.2024"), None);
+        assert_eq!(diagnosis_period("2024"), None);
+        assert_eq!(diagnosis_period(""), None);
+    }
+
+    #[test]
+    fn should_select_conditions_within_diagnosis_period() {
+        let entries = [row(1, 1, "C17.1", "TEST"), row(2, 1, "C61", "TEST")]
+            .iter()
+            .map(entry)
+            .collect::<Vec<_>>();
+        let entries = entries.iter().collect::<Vec<_>>();
+
+        let june = DiagnosisPeriod::Range(date(2024, 6, 1), date(2024, 6, 30));
+        let may = DiagnosisPeriod::Range(date(2024, 5, 1), date(2024, 5, 31));
+
+        assert_eq!(june.years(), vec!["2024".to_string()]);
+        assert_eq!(
+            conditions(&entries, &june, "9999-12-31", false, false, false, false).len(),
+            2
+        );
+        assert!(conditions(&entries, &may, "9999-12-31", false, false, false, false).is_empty());
+
+        assert_eq!(june.contains_date("00.06.2024"), Some(true));
+        assert_eq!(june.contains_date("00.00.2024"), Some(false));
+        assert_eq!(may.contains_date("2024-05-31"), Some(true));
+        assert_eq!(may.contains_date("unbekannt"), None);
+    }
+
+    #[test]
+    fn should_explain_excluded_entries() {
+        let entries = [
+            row(1, 1, "C17.1", "TEST"),
+            row(2, 2, "C61", "TEST"),
+            row(3, 3, "C61", "TEST9999"),
+        ]
+        .iter()
+        .map(entry)
+        .collect::<Vec<_>>();
+        let entries = entries.iter().collect::<Vec<_>>();
+        let may = DiagnosisPeriod::Range(date(2024, 5, 1), date(2024, 5, 31));
+
+        let actual = condition_exclusions(&entries, None, "9999-12-31", false, false, false);
+
+        assert_eq!(actual[0].reasons, vec![ExclusionReason::OutdatedVersion]);
+        assert_eq!(actual[1].reasons, vec![ExclusionReason::OutdatedVersion]);
+        assert_eq!(actual[2].reasons, vec![ExclusionReason::Extern]);
+        assert!(!actual.iter().any(|exclusion| exclusion.is_selected()));
+
+        let actual = condition_exclusions(&entries, Some(&may), "9999-12-31", false, true, false);
+
+        assert_eq!(actual[2].reasons, vec![ExclusionReason::DiagnosisPeriod]);
+    }
+
+    #[test]
+    fn should_use_configured_extern_rule() {
+        let rules = EntryRules {
+            extern_rule: ExternRule::new(&["^EXT-".into()], &["M42".into()], &[]).unwrap(),
+            ..Default::default()
+        };
+        let mut extern_row = row(1, 1, "C61", "TEST");
+        extern_row.xml_daten = extern_row
+            .xml_daten
+            .replace("Meldung_ID=", "Melder_ID=\"M42\" Meldung_ID=");
+
+        assert!(!MeldungEntry::new(&row(1, 1, "C61", "TEST9999"), &rules).is_extern);
+        assert!(MeldungEntry::new(&row(1, 1, "C61", "EXT-TEST"), &rules).is_extern);
+        assert!(MeldungEntry::new(&extern_row, &rules).is_extern);
+        assert!(ExternRule::new(&["(".into()], &[], &[]).is_err());
+    }
+
+    #[test]
+    fn should_count_conditions_by_meldende_stelle() {
+        let entries = [
+            row(1, 1, "C17.1", "TEST"),
+            row(2, 1, "C61", "TEST"),
+            row(3, 1, "C50.1", "TEST9999"),
+        ]
+        .iter()
+        .map(entry)
+        .collect::<Vec<_>>();
+        let entries = entries.iter().collect::<Vec<_>>();
+        let year = DiagnosisPeriod::Year("2024".into());
+
+        let actual = meldende_stellen(&entries, &year, "9999-12-31", false, true, false);
+
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[0].name, "TEST");
+        assert_eq!(actual[0].size, 2);
+        assert!(!actual[0].is_extern);
+        assert_eq!(actual[1].name, "TEST9999");
+        assert_eq!(actual[1].size, 1);
+        assert!(actual[1].is_extern);
+    }
+}
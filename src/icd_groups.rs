@@ -0,0 +1,476 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::filter::DiagnosisPeriod;
+use crate::resources::ICD_GROUPS;
+
+/// Name of the group of all codes not listed in any group
+pub const OTHER: &str = "Other";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Definition {
+    version: Vec<VersionDefinition>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VersionDefinition {
+    name: String,
+    valid_from: Option<u16>,
+    group: Vec<GroupDefinition>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupDefinition {
+    name: String,
+    codes: Vec<String>,
+}
+
+/// All versions of the grouping, each valid for diagnosis years from `valid_from`
+pub struct IcdGroupVersions(Vec<IcdGroups>);
+
+/// Mapping of ICD-10 codes to BZKF groups in one version of the grouping
+#[derive(Clone)]
+pub struct IcdGroups {
+    pub version: String,
+    valid_from: u16,
+    names: Vec<String>,
+    categories: HashMap<String, usize>,
+    codes: HashMap<String, usize>,
+}
+
+impl Default for IcdGroupVersions {
+    fn default() -> Self {
+        Self::parse(ICD_GROUPS).expect("valid embedded ICD-10 group definition")
+    }
+}
+
+impl IcdGroupVersions {
+    /// Loads the group definition from given file or uses the embedded definition
+    pub fn load(path: Option<&Path>) -> Result<IcdGroupVersions, String> {
+        match path {
+            Some(path) => {
+                let content = fs::read_to_string(path).map_err(|_| {
+                    format!(
+                        "Kann Datei mit ICD-10-Gruppen '{}' nicht lesen",
+                        path.to_str().unwrap_or_default()
+                    )
+                })?;
+                Self::parse(&content).map_err(|err| {
+                    format!(
+                        "Ungültige Datei mit ICD-10-Gruppen '{}': {}",
+                        path.to_str().unwrap_or_default(),
+                        err
+                    )
+                })
+            }
+            None => Ok(IcdGroupVersions::default()),
+        }
+    }
+
+    fn parse(content: &str) -> Result<IcdGroupVersions, String> {
+        let definition =
+            toml::from_str::<Definition>(content).map_err(|err| err.message().to_string())?;
+
+        let mut versions: Vec<IcdGroups> = vec![];
+        for version in &definition.version {
+            if versions.iter().any(|other| other.version == version.name) {
+                return Err(format!("Doppelte Version '{}'", version.name));
+            }
+            versions.push(IcdGroups::new(version)?);
+        }
+
+        if versions.is_empty() {
+            return Err("Keine Version der ICD-10-Gruppen angegeben".into());
+        }
+
+        Ok(IcdGroupVersions(versions))
+    }
+
+    /// Selects the version with given name or the latest version valid for the given
+    /// diagnosis year. Without name and year, the latest version is used.
+    pub fn select(&self, name: Option<&str>, year: Option<&str>) -> Result<IcdGroups, String> {
+        if let Some(name) = name {
+            return self
+                .0
+                .iter()
+                .find(|groups| groups.version == name)
+                .cloned()
+                .ok_or(format!(
+                    "Version der ICD-10-Gruppen '{}' nicht gefunden",
+                    name
+                ));
+        }
+
+        let year = match year {
+            Some(year) => year
+                .parse::<u16>()
+                .map_err(|_| format!("Ungültiges Diagnosejahr '{}'", year))?,
+            None => u16::MAX,
+        };
+
+        self.0
+            .iter()
+            .filter(|groups| groups.valid_from <= year)
+            .max_by_key(|groups| groups.valid_from)
+            .cloned()
+            .ok_or(format!(
+                "Keine Version der ICD-10-Gruppen für das Diagnosejahr {} vorhanden",
+                year
+            ))
+    }
+
+    /// Selects the version with given name or the latest version valid for the given
+    /// diagnosis period
+    pub fn select_for_period(
+        &self,
+        name: Option<&str>,
+        period: &DiagnosisPeriod,
+    ) -> Result<IcdGroups, String> {
+        self.select(name, Some(&period.grouping_year()))
+    }
+}
+
+impl Default for IcdGroups {
+    fn default() -> Self {
+        IcdGroupVersions::default()
+            .select(None, None)
+            .expect("valid embedded ICD-10 group definition")
+    }
+}
+
+impl IcdGroups {
+    fn new(definition: &VersionDefinition) -> Result<IcdGroups, String> {
+        let category_re = Regex::new(r"^(?<letter>[A-Z])(?<number>[0-9]{2})$").unwrap();
+        let code_re = Regex::new(r"^[A-Z][0-9]{2}\.[0-9A-Z]+$").unwrap();
+
+        let mut groups = IcdGroups {
+            version: definition.name.to_string(),
+            valid_from: definition.valid_from.unwrap_or_default(),
+            names: vec![],
+            categories: HashMap::new(),
+            codes: HashMap::new(),
+        };
+
+        for (index, group) in definition.group.iter().enumerate() {
+            if group.name == OTHER || groups.names.contains(&group.name) {
+                return Err(format!(
+                    "Ungültiger oder doppelter Gruppenname '{}' in Version '{}'",
+                    group.name, definition.name
+                ));
+            }
+            groups.names.push(group.name.to_string());
+
+            for code in &group.codes {
+                let (target, values) = match code_re.is_match(code) {
+                    true => (&mut groups.codes, vec![code.to_string()]),
+                    false => (
+                        &mut groups.categories,
+                        Self::categories(&category_re, code)
+                            .ok_or(format!("Ungültiger ICD-10-Code '{}'", code))?,
+                    ),
+                };
+
+                for value in values {
+                    if target.insert(value.to_string(), index).is_some() {
+                        return Err(format!(
+                            "ICD-10-Code '{}' ist in Version '{}' mehreren Gruppen zugeordnet",
+                            value, definition.name
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Expands a category like "C50" or a range of categories like "C00-C14"
+    fn categories(category_re: &Regex, value: &str) -> Option<Vec<String>> {
+        let category = |value: &str| {
+            category_re.captures(value).map(|caps| {
+                (
+                    caps["letter"].to_string(),
+                    caps["number"].parse::<u8>().unwrap_or_default(),
+                )
+            })
+        };
+
+        let ((letter, start), (end_letter, end)) = match value.split_once('-') {
+            Some((start, end)) => (category(start)?, category(end)?),
+            None => (category(value)?, category(value)?),
+        };
+
+        if letter != end_letter || start > end {
+            return None;
+        }
+
+        Some(
+            (start..=end)
+                .map(|number| format!("{}{:02}", letter, number))
+                .collect(),
+        )
+    }
+
+    /// Name of the group of given code. Complete codes listed in the definition take precedence
+    /// over the category of the code.
+    pub fn group_name(&self, code: &str) -> &str {
+        let category = code.split('.').next().unwrap_or_default();
+
+        match self
+            .codes
+            .get(code)
+            .or_else(|| self.categories.get(category))
+        {
+            Some(&index) => &self.names[index],
+            None => OTHER,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::filter::DiagnosisPeriod;
+    use crate::icd_groups::{IcdGroupVersions, IcdGroups, OTHER};
+
+    /// Mapping used before the group definition was moved into `icd-groups.toml`
+    fn previous_group_name(code: &str) -> &str {
+        let icd10 = match code {
+            "D39.1" | "D09.0" | "D41.4" => code,
+            _ => code.split('.').next().unwrap(),
+        };
+
+        match icd10 {
+            "C00" | "C01" | "C02" | "C03" | "C04" | "C05" | "C06" | "C07" | "C08" | "C09"
+            | "C10" | "C11" | "C12" | "C13" | "C14" => "C00-C14",
+            "C15" => "C15",
+            "C16" => "C16",
+            "C18" | "C19" | "C20" | "C21" => "C18-C21",
+            "C22" => "C22",
+            "C23" | "C24" => "C23-C24",
+            "C25" => "C25",
+            "C32" => "C32",
+            "C33" | "C34" => "C33-C34",
+            "C43" => "C43",
+            "C50" | "D05" => "C50, D05",
+            "C53" | "D06" => "C53, D06",
+            "C54" | "C55" => "C54-C55",
+            "C56" | "D39.1" => "C56, D39.1",
+            "C61" => "C61",
+            "C62" => "C62",
+            "C64" => "C64",
+            "C67" | "D09.0" | "D41.4" => "C67, D09.0, D41.4",
+            "C70" | "C71" | "C72" => "C70-C72",
+            "C73" => "C73",
+            "C81" => "C81",
+            "C82" | "C83" | "C84" | "C85" | "C86" | "C87" | "C88" | "C96" => "C82-C88, C96",
+            "C90" => "C90",
+            "C91" | "C92" | "C93" | "C94" | "C95" => "C91-C95",
+            _ => "Other",
+        }
+    }
+
+    #[test]
+    fn should_map_all_c00_to_d48_codes_as_before() {
+        let groups = IcdGroups::default();
+
+        let codes = ('C'..='D')
+            .flat_map(|letter| {
+                (0..=if letter == 'C' { 99 } else { 48 })
+                    .map(move |number| format!("{}{:02}", letter, number))
+            })
+            .flat_map(|category| {
+                let mut codes = vec![category.to_string()];
+                codes.extend((0..=9).map(|subcode| format!("{}.{}", category, subcode)));
+                codes.push(format!("{}.10", category));
+                codes
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(codes.len(), 149 * 12);
+        codes.iter().for_each(|code| {
+            assert_eq!(
+                groups.group_name(code),
+                previous_group_name(code),
+                "{}",
+                code
+            )
+        });
+    }
+
+    #[test]
+    fn should_use_embedded_version_of_diagnosis_year() {
+        let versions = IcdGroupVersions::default();
+
+        let previous = versions.select(None, Some("2022")).unwrap();
+        assert_eq!(previous.version, "2020");
+        assert_eq!(previous.group_name("C50.1"), "C50");
+        assert_eq!(previous.group_name("D05.1"), OTHER);
+
+        let current = versions.select(None, Some("2023")).unwrap();
+        assert_eq!(current.version, "2024");
+        assert_eq!(current.group_name("D05.1"), "C50, D05");
+    }
+
+    #[test]
+    fn should_use_custom_definition() {
+        let groups = IcdGroupVersions::parse(
+            r#"
+            [[version]]
+            name = "test"
+
+            [[version.group]]
+            name = "C00-C09, D00.1"
+            codes = ["C00-C09", "D00.1"]
+            "#,
+        )
+        .unwrap()
+        .select(None, None)
+        .unwrap();
+
+        assert_eq!(groups.group_name("C09.9"), "C00-C09, D00.1");
+        assert_eq!(groups.group_name("D00.1"), "C00-C09, D00.1");
+        assert_eq!(groups.group_name("D00.2"), OTHER);
+        assert_eq!(groups.group_name("C10"), OTHER);
+    }
+
+    #[test]
+    fn should_select_version_by_name_or_year() {
+        let versions = || {
+            IcdGroupVersions::parse(
+                r#"
+                [[version]]
+                name = "2020"
+                valid_from = 2020
+
+                [[version.group]]
+                name = "C50"
+                codes = ["C50"]
+
+                [[version]]
+                name = "2023"
+                valid_from = 2023
+
+                [[version.group]]
+                name = "C50, D05"
+                codes = ["C50", "D05"]
+                "#,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            versions().select(None, Some("2022")).unwrap().version,
+            "2020"
+        );
+        assert_eq!(
+            versions().select(None, Some("2023")).unwrap().version,
+            "2023"
+        );
+        assert_eq!(versions().select(None, None).unwrap().version, "2023");
+        assert_eq!(
+            versions()
+                .select(Some("2020"), Some("2024"))
+                .unwrap()
+                .version,
+            "2020"
+        );
+        assert!(versions().select(None, Some("2019")).is_err());
+        assert!(versions().select(Some("2021"), None).is_err());
+    }
+
+    #[test]
+    fn should_select_version_for_each_period() {
+        let versions = IcdGroupVersions::parse(
+            r#"
+            [[version]]
+            name = "2020"
+            valid_from = 2020
+
+            [[version.group]]
+            name = "C50"
+            codes = ["C50"]
+
+            [[version]]
+            name = "2023"
+            valid_from = 2023
+
+            [[version.group]]
+            name = "C50, D05"
+            codes = ["C50", "D05"]
+            "#,
+        )
+        .unwrap();
+        let periods = [
+            DiagnosisPeriod::Year("2022".into()),
+            DiagnosisPeriod::Year("2023".into()),
+            DiagnosisPeriod::Range(
+                NaiveDate::from_ymd_opt(2022, 7, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2022, 12, 31).unwrap(),
+            ),
+        ];
+
+        let actual = periods
+            .iter()
+            .map(|period| versions.select_for_period(None, period).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(actual[0].version, "2020");
+        assert_eq!(actual[0].group_name("D05.1"), OTHER);
+        assert_eq!(actual[1].version, "2023");
+        assert_eq!(actual[1].group_name("D05.1"), "C50, D05");
+        assert_eq!(actual[2].version, "2020");
+        assert_eq!(
+            versions
+                .select_for_period(Some("2023"), &periods[0])
+                .unwrap()
+                .version,
+            "2023"
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_definition() {
+        let parse = |codes: &str| {
+            IcdGroupVersions::parse(&format!("[[version]]\nname = \"test\"\n{}", codes))
+        };
+
+        assert!(parse("[[version.group]]\nname = \"A\"\ncodes = [\"C10-C01\"]").is_err());
+        assert!(parse("[[version.group]]\nname = \"A\"\ncodes = [\"C10-D11\"]").is_err());
+        assert!(parse("[[version.group]]\nname = \"A\"\ncodes = [\"C1\"]").is_err());
+        assert!(parse(
+            "[[version.group]]\nname = \"A\"\ncodes = [\"C10\"]\n[[version.group]]\nname = \"B\"\ncodes = [\"C08-C12\"]"
+        )
+        .is_err());
+        assert!(parse("[[version.group]]\nname = \"Other\"\ncodes = [\"C10\"]").is_err());
+        assert!(IcdGroupVersions::parse("version = []").is_err());
+    }
+}
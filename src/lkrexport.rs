@@ -18,104 +18,455 @@
  * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
  */
 
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
 use std::fs;
-use std::path::Path;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use chrono::NaiveDate;
 use itertools::Itertools;
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
 use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use crate::canonical;
+use crate::canonical::{CanonicalElement, XmlChange};
+use crate::common::{ExportData, MeldungExport};
+use crate::filter;
+use crate::filter::{DiagnosisPeriod, EntryRules, MeldungEntry};
+
+/// Identifier system used by the ETL at Erlangen
+const CONDITION_ID_SYSTEM: &str =
+    "https://fhir.diz.uk-erlangen.de/identifiers/onkostar-xml-condition-id";
+
+/// Composition of the hashed value as used by the former SQL queries
+const CONDITION_ID_PATTERN: &str = "{system}|{patient_id}condition{tumor_id}";
+
+/// Placeholders available in the composition pattern
+const CONDITION_ID_PLACEHOLDERS: [&str; 3] = ["system", "patient_id", "tumor_id"];
+
+/// Composition of the value hashed with SHA-256 to get the condition ID
+#[derive(Debug)]
+pub struct ConditionIdScheme {
+    system: String,
+    pattern: String,
+}
+
+impl Default for ConditionIdScheme {
+    fn default() -> Self {
+        ConditionIdScheme {
+            system: CONDITION_ID_SYSTEM.to_string(),
+            pattern: CONDITION_ID_PATTERN.to_string(),
+        }
+    }
+}
+
+impl ConditionIdScheme {
+    /// Uses the given identifier system and pattern with placeholders `{system}`,
+    /// `{patient_id}` and `{tumor_id}`
+    pub fn new(system: Option<&str>, pattern: Option<&str>) -> Result<Self, String> {
+        let pattern = pattern.unwrap_or(CONDITION_ID_PATTERN);
+
+        let placeholder_re = Regex::new(r"\{(?<name>[^}]*)}").unwrap();
+        if let Some(unknown) = placeholder_re
+            .captures_iter(pattern)
+            .map(|caps| caps["name"].to_string())
+            .find(|name| !CONDITION_ID_PLACEHOLDERS.contains(&name.as_str()))
+        {
+            return Err(format!(
+                "Unbekannter Platzhalter '{{{}}}' im Muster der Condition-ID",
+                unknown
+            ));
+        }
+        if !pattern.contains("{patient_id}") || !pattern.contains("{tumor_id}") {
+            return Err(
+                "Muster der Condition-ID muss '{patient_id}' und '{tumor_id}' enthalten".into(),
+            );
+        }
+
+        Ok(ConditionIdScheme {
+            system: system.unwrap_or(CONDITION_ID_SYSTEM).to_string(),
+            pattern: pattern.to_string(),
+        })
+    }
+
+    pub fn condition_id(&self, patient_id: &str, tumor_id: &str) -> String {
+        let value = self
+            .pattern
+            .replace("{system}", &self.system)
+            .replace("{patient_id}", patient_id)
+            .replace("{tumor_id}", tumor_id);
+        format!("{:x}", Sha256::digest(value))
+    }
+}
+
+#[derive(Debug)]
+pub enum LkrExportError {
+    /// Protocol file could not be read
+    Io(PathBuf, std::io::Error),
+    /// Content is not well-formed XML
+    Xml(String),
+    /// Content does not contain any patient
+    NoPatients,
+}
+
+impl Display for LkrExportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LkrExportError::Io(path, err) if err.kind() == ErrorKind::NotFound => write!(
+                f,
+                "Protokolldatei '{}' nicht gefunden",
+                path.to_str().unwrap_or_default()
+            ),
+            LkrExportError::Io(path, err) => write!(
+                f,
+                "Kann Protokolldatei '{}' nicht lesen: {}",
+                path.to_str().unwrap_or_default(),
+                err
+            ),
+            LkrExportError::Xml(err) => {
+                write!(f, "Ungültiges XML im LKR-Export-Protokoll: {}", err)
+            }
+            LkrExportError::NoPatients => {
+                write!(f, "Keine Patienten im LKR-Export-Protokoll gefunden")
+            }
+        }
+    }
+}
+
+impl Error for LkrExportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LkrExportError::Io(_, err) => Some(err),
+            LkrExportError::Xml(_) | LkrExportError::NoPatients => None,
+        }
+    }
+}
 
 pub struct LkrExportProtocolFile {
     pub patients: Vec<Patient>,
 }
 
 impl LkrExportProtocolFile {
-    pub fn parse_file(path: &Path) -> Result<LkrExportProtocolFile, ()> {
-        let xml_file_content = fs::read_to_string(path).map_err(|_| ())?;
+    pub fn parse_file(path: &Path) -> Result<LkrExportProtocolFile, LkrExportError> {
+        let xml_file_content =
+            fs::read_to_string(path).map_err(|err| LkrExportError::Io(path.to_path_buf(), err))?;
         Self::parse(&xml_file_content)
     }
 
-    pub fn parse(content: &str) -> Result<LkrExportProtocolFile, ()> {
-        let re = Regex::new(r"(?s)(?<patient><Patient>(.*?)</Patient>)").unwrap();
+    /// Parses all `Patient` elements and their `Meldung` elements regardless of namespace prefix.
+    /// Raw fragments are taken unchanged from the content.
+    pub fn parse(content: &str) -> Result<LkrExportProtocolFile, LkrExportError> {
+        let mut reader = Reader::from_str(content);
+        let mut patients = vec![];
 
-        if re.is_match(content) {
-            let patients = re
-                .find_iter(content)
-                .map(|m| Patient {
-                    raw_value: m.as_str().to_string(),
-                })
-                .collect_vec();
-            return Ok(LkrExportProtocolFile { patients });
+        let mut schema_version = String::new();
+        let mut absender_id = String::new();
+        let mut in_patient = false;
+        let mut patient_id = String::new();
+        let mut meldungen = vec![];
+        let mut meldung_start = None;
+        // Number of nested `Meldung` elements within the current Meldung
+        let mut meldung_depth = 0;
+
+        loop {
+            let position = reader.buffer_position() as usize;
+            let event = reader.read_event().map_err(|err| {
+                LkrExportError::Xml(format!("{} an Position {}", err, reader.error_position()))
+            })?;
+            match event {
+                Event::Start(ref element) if element.local_name().as_ref() == b"ADT_GEKID" => {
+                    schema_version = attribute(element, "Schema_Version").unwrap_or_default();
+                }
+                Event::Start(ref element) | Event::Empty(ref element)
+                    if !in_patient && element.local_name().as_ref() == b"Absender" =>
+                {
+                    absender_id = attribute(element, "Absender_ID").unwrap_or_default();
+                }
+                Event::Start(ref element) | Event::Empty(ref element)
+                    if in_patient && element.local_name().as_ref() == b"Patienten_Stammdaten" =>
+                {
+                    patient_id = attribute(element, "Patient_ID").unwrap_or_default();
+                }
+                Event::Start(ref element)
+                    if meldung_start.is_some() && element.local_name().as_ref() == b"Meldung" =>
+                {
+                    meldung_depth += 1;
+                }
+                Event::Start(ref element) if meldung_start.is_none() => {
+                    match element.local_name().as_ref() {
+                        b"Patient" if !in_patient => {
+                            in_patient = true;
+                            patient_id = String::new();
+                        }
+                        b"Meldung" if in_patient => {
+                            meldung_start = Some(position);
+                            meldung_depth = 0;
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Empty(ref element)
+                    if in_patient
+                        && meldung_start.is_none()
+                        && element.local_name().as_ref() == b"Meldung" =>
+                {
+                    let end = reader.buffer_position() as usize;
+                    meldungen.push(Meldung::new(&content[position..end]));
+                }
+                Event::End(ref element) => match (element.local_name().as_ref(), meldung_start) {
+                    (b"Meldung", Some(start)) if meldung_depth == 0 => {
+                        let end = reader.buffer_position() as usize;
+                        meldungen.push(Meldung::new(&content[start..end]));
+                        meldung_start = None;
+                    }
+                    (b"Meldung", Some(_)) => meldung_depth -= 1,
+                    (b"Patient", None) if in_patient => {
+                        in_patient = false;
+                        patients.push(Patient {
+                            schema_version: schema_version.to_string(),
+                            absender_id: absender_id.to_string(),
+                            patient_id: std::mem::take(&mut patient_id),
+                            meldungen: std::mem::take(&mut meldungen),
+                        });
+                    }
+                    _ => {}
+                },
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        if patients.is_empty() {
+            return Err(LkrExportError::NoPatients);
+        }
+
+        Ok(LkrExportProtocolFile { patients })
+    }
+
+    /// Parses a single protocol file or all XML files of a directory
+    pub fn parse_path(path: &Path) -> Result<LkrExportProtocolFile, LkrExportError> {
+        if !path.is_dir() {
+            return Self::parse_file(path);
+        }
+
+        let files = fs::read_dir(path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|err| LkrExportError::Io(path.to_path_buf(), err))?
+            .into_iter()
+            .filter(|file| {
+                file.extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("xml"))
+            })
+            .sorted()
+            .collect_vec();
+
+        let mut patients = vec![];
+        for file in files {
+            patients.extend(Self::parse_file(&file)?.patients);
+        }
+
+        if patients.is_empty() {
+            return Err(LkrExportError::NoPatients);
         }
 
-        Err(())
+        Ok(LkrExportProtocolFile { patients })
     }
 
-    pub fn meldungen(&self) -> Vec<Meldung> {
+    pub fn meldungen(&self) -> Vec<&Meldung> {
         self.patients
             .iter()
-            .flat_map(|patient| patient.meldungen())
+            .flat_map(|patient| patient.meldungen.iter())
             .collect_vec()
     }
+
+    /// Conditions of all Meldungen within the given period, selected by the rules used for the
+    /// database. The condition ID uses the tumor ID of `Diagnose` or `Tumorzuordnung`.
+    pub fn conditions(
+        &self,
+        period: &DiagnosisPeriod,
+        rules: &EntryRules,
+        ignore_non_obds_2: bool,
+        use_pat_id: bool,
+        include_extern: bool,
+        include_histo_zyto: bool,
+    ) -> Vec<ExportData> {
+        let entries = self
+            .patients
+            .iter()
+            .flat_map(|patient| {
+                patient
+                    .meldungen
+                    .iter()
+                    .map(move |meldung| (patient, meldung))
+            })
+            .filter(|(_, meldung)| !meldung.tumorzuordnung_id().is_empty())
+            .map(|(patient, meldung)| MeldungEntry {
+                condition_id: rules
+                    .condition_id
+                    .condition_id(&patient.patient_id, &meldung.tumorzuordnung_id()),
+                ..MeldungEntry::new(&patient.meldung_export(meldung), rules)
+            })
+            .collect_vec();
+
+        filter::protocol_conditions(
+            &entries,
+            period,
+            ignore_non_obds_2,
+            use_pat_id,
+            include_extern,
+            include_histo_zyto,
+        )
+    }
 }
 
+/// `Patient` element with the content of `Patienten_Stammdaten@Patient_ID` and the schema
+/// version and sender of the protocol file
 pub struct Patient {
-    pub raw_value: String,
+    pub schema_version: String,
+    pub absender_id: String,
+    pub patient_id: String,
+    pub meldungen: Vec<Meldung>,
 }
 
 impl Patient {
-    pub fn meldungen(&self) -> Vec<Meldung> {
-        let re = Regex::new(r"(?s)(?<meldung><Meldung(.*?)</Meldung>)").unwrap();
-
-        if re.is_match(&self.raw_value) {
-            return re
-                .find_iter(&self.raw_value)
-                .map(|m| Meldung {
-                    raw_value: m.as_str().to_string(),
-                })
-                .collect_vec();
+    /// Meldung of this patient like a row of `lkr_meldung_export` with a document containing
+    /// this Meldung only. Meldungen of protocol files are exported and have no version.
+    pub fn meldung_export(&self, meldung: &Meldung) -> MeldungExport {
+        MeldungExport {
+            id: 0,
+            lkr_meldung: None,
+            lkr_export: None,
+            typ: Some(0),
+            versionsnummer: None,
+            xml_daten: format!(
+                r#"<ADT_GEKID Schema_Version="{}"><Absender Absender_ID="{}"/><Menge_Patient><Patient><Patienten_Stammdaten Patient_ID="{}"/><Menge_Meldung>{}</Menge_Meldung></Patient></Menge_Patient></ADT_GEKID>"#,
+                escape(&self.schema_version),
+                escape(&self.absender_id),
+                escape(&self.patient_id),
+                meldung.raw_value
+            ),
+            exportiert_am: None,
         }
-        vec![]
     }
 }
 
+/// `Meldung` element or, if read from the database, a whole XML document
 pub struct Meldung {
     pub raw_value: String,
+    values: XmlValues,
 }
 
 impl FromStr for Meldung {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Meldung {
-            raw_value: s.to_string(),
-        })
+        Ok(Meldung::new(s))
     }
 }
 
 #[allow(unused)]
 impl Meldung {
-    pub fn id(&self) -> Option<String> {
-        let re = Regex::new(r#"Meldung_ID="(?<meldung_id>(.*?))""#).unwrap();
-
-        if re.is_match(&self.raw_value) {
-            let caps = re.captures(&self.raw_value).unwrap();
-            return Some(caps["meldung_id"].to_string());
+    /// Parses values of the given XML content. Values after malformed XML are ignored.
+    pub fn new(raw_value: &str) -> Self {
+        Meldung {
+            raw_value: raw_value.to_string(),
+            values: XmlValues::parse(raw_value),
         }
+    }
 
-        None
+    pub fn id(&self) -> Option<String> {
+        self.values.first_attribute("Meldung", "Meldung_ID")
     }
 
     pub fn icd10(&self) -> Option<String> {
-        let re = Regex::new(r"(?s)<Primaertumor_ICD_Code>(?<icd10>(.*?))</Primaertumor_ICD_Code>")
-            .unwrap();
+        self.values.first_element("Primaertumor_ICD_Code")
+    }
 
-        if re.is_match(&self.raw_value) {
-            let caps = re.captures(&self.raw_value).unwrap();
-            return Some(caps["icd10"].to_string());
+    /// Patient ID like `EXTRACTVALUE(xml, '//Patienten_Stammdaten/@Patient_ID')`
+    pub fn patient_id(&self) -> String {
+        self.values.attribute("Patienten_Stammdaten", "Patient_ID")
+    }
+
+    /// Tumor ID like `EXTRACTVALUE(xml, '//Diagnose/@Tumor_ID')`
+    pub fn tumor_id(&self) -> String {
+        self.values.attribute("Diagnose", "Tumor_ID")
+    }
+
+    /// Tumor ID of `Diagnose` or, if missing, of `Tumorzuordnung` as used in protocol files
+    pub fn tumorzuordnung_id(&self) -> String {
+        match self.tumor_id() {
+            tumor_id if tumor_id.is_empty() => self.values.attribute("Tumorzuordnung", "Tumor_ID"),
+            tumor_id => tumor_id,
         }
+    }
 
-        None
+    /// Checks if any element name contains `ICD_Version`, e.g. `Primaertumor_ICD_Version`
+    pub fn has_icd_version(&self) -> bool {
+        self.values.has_element_containing("ICD_Version")
+    }
+
+    /// Checks if there is a `Menge_Tumorkonferenz` element
+    pub fn has_tumorkonferenz(&self) -> bool {
+        self.values.has_element("Menge_Tumorkonferenz")
+    }
+
+    /// oBDS schema version like `EXTRACTVALUE(xml, '//ADT_GEKID/@Schema_Version')`
+    pub fn schema_version(&self) -> String {
+        self.values.attribute("ADT_GEKID", "Schema_Version")
+    }
+
+    /// First word of all ICD-10 codes as used by the former SQL queries
+    pub fn primary_icd10_code(&self) -> String {
+        first_word(&self.values.element("Primaertumor_ICD_Code"))
+    }
+
+    /// First word of all diagnosis dates
+    pub fn diagnosis_date(&self) -> String {
+        first_word(&self.values.element("Diagnosedatum"))
+    }
+
+    /// Year of diagnosis, the part after the last dot of the diagnosis date
+    pub fn diagnosis_year(&self) -> String {
+        self.diagnosis_date()
+            .rsplit('.')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Date of the first `Meldedatum`, if valid
+    pub fn meldedatum(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(&first_word(&self.values.element("Meldedatum")), "%d.%m.%Y").ok()
+    }
+
+    /// Content of all `Meldende_Stelle` elements
+    pub fn meldende_stelle(&self) -> String {
+        self.values.element("Meldende_Stelle")
+    }
+
+    /// Melder ID like `EXTRACTVALUE(xml, '//Meldung/@Melder_ID')`
+    pub fn melder_id(&self) -> String {
+        self.values.attribute("Meldung", "Melder_ID")
+    }
+
+    /// Sender ID like `EXTRACTVALUE(xml, '//Absender/@Absender_ID')`
+    pub fn absender_id(&self) -> String {
+        self.values.attribute("Absender", "Absender_ID")
+    }
+
+    /// Condition ID as SHA-256 hash of patient ID and tumor ID
+    pub fn condition_id(&self, scheme: &ConditionIdScheme) -> String {
+        scheme.condition_id(&self.patient_id(), &self.tumor_id())
     }
 
     pub fn database_id(&self) -> Option<String> {
@@ -125,25 +476,285 @@ impl Meldung {
         }
     }
 
-    pub fn sanitized_xml_string(&self) -> String {
-        let re = Regex::new(r"[\r|\n]+\s*").unwrap();
-        let content = re.replace_all(&self.raw_value, "").trim().to_string();
+    /// Canonical form of the Meldung used to compare its content
+    pub fn canonical(&self) -> Result<CanonicalElement, String> {
+        CanonicalElement::parse(&self.raw_value)
+    }
+
+    /// Checks if both Meldungen have the same content in canonical form. Malformed XML is
+    /// compared as raw value.
+    pub fn same_content(&self, other: &Meldung) -> bool {
+        match (self.canonical(), other.canonical()) {
+            (Ok(canonical), Ok(other_canonical)) => canonical == other_canonical,
+            _ => self.raw_value == other.raw_value,
+        }
+    }
+}
+
+/// Result of the comparison between database entries of an LKR export and the protocol file
+pub struct ExportCheck {
+    pub db_entries: usize,
+    pub db_meldungen: usize,
+    pub xml_meldungen: usize,
+    pub missing_in_db: Vec<String>,
+    pub missing_in_xml: Vec<String>,
+    pub multiple_meldungen: Vec<String>,
+    pub different_content: Vec<String>,
+    pub content_diffs: Vec<ContentDiff>,
+}
+
+/// Meldung with different content in the protocol file and the database
+pub struct ContentDiff {
+    pub meldung_id: String,
+    pub protocol_xml: String,
+    pub database_xml: String,
+}
+
+impl ContentDiff {
+    /// Canonical form of the Meldung in the protocol file and in the database
+    pub fn canonical(&self) -> Result<(CanonicalElement, CanonicalElement), String> {
+        Ok((
+            CanonicalElement::parse(&self.protocol_xml)?,
+            CanonicalElement::parse(&self.database_xml)?,
+        ))
+    }
+
+    /// Changes from the exported Meldung in the protocol file to the current Meldung in the
+    /// database
+    pub fn changes(&self) -> Result<Vec<XmlChange>, String> {
+        self.canonical()
+            .map(|(protocol, database)| canonical::diff(&protocol, &database))
+    }
+}
+
+impl ExportCheck {
+    /// Compares entries of `lkr_meldung_export` as tuples of ID and XML data with the protocol file
+    pub fn new(db_entries: &[(String, String)], protocol_file: &LkrExportProtocolFile) -> Self {
+        let db_meldungen = db_entries
+            .iter()
+            .filter_map(|entry| LkrExportProtocolFile::parse(&entry.1).ok())
+            .flat_map(|content| content.patients)
+            .flat_map(|patient| patient.meldungen)
+            .filter_map(|meldung| meldung.id().map(|id| (id, meldung)))
+            .collect::<HashMap<_, _>>();
+
+        let xml_meldungen = protocol_file
+            .meldungen()
+            .into_iter()
+            .filter(|meldung| meldung.id().is_some())
+            .map(|meldung| (meldung.id().unwrap(), meldung))
+            .collect::<HashMap<_, _>>();
 
-        let re = Regex::new(r"<[^>]+/>").unwrap();
-        if re.is_match(&content) {
-            let mut c = content.to_string();
-            re.find_iter(&content)
-                .map(|m| m.as_str().to_string().replace('<', "").replace("/>", ""))
-                .for_each(|tag| {
-                    c = c.replace(&format!("<{}/>", tag), &format!("<{}></{}>", tag, tag));
-                });
-            return c;
+        let missing_in_xml = db_meldungen
+            .keys()
+            .filter(|&key| !xml_meldungen.contains_key(key))
+            .sorted()
+            .cloned()
+            .collect_vec();
+
+        let missing_in_db = xml_meldungen
+            .keys()
+            .filter(|&key| !db_meldungen.contains_key(key))
+            .sorted()
+            .cloned()
+            .collect_vec();
+
+        let multiple_meldungen = db_entries
+            .iter()
+            .filter(|(_, xml_data)| {
+                LkrExportProtocolFile::parse(xml_data)
+                    .map(|content| content.meldungen().len() > 1)
+                    .unwrap_or_default()
+            })
+            .map(|(lkr_meldung, _)| lkr_meldung.to_string())
+            .sorted()
+            .collect_vec();
+
+        let content_diffs = db_meldungen
+            .iter()
+            .filter_map(|(id, meldung)| {
+                let xml_meldung = xml_meldungen.get(id.as_str())?;
+                (!xml_meldung.same_content(meldung)).then(|| ContentDiff {
+                    meldung_id: id.to_string(),
+                    protocol_xml: xml_meldung.raw_value.to_string(),
+                    database_xml: meldung.raw_value.to_string(),
+                })
+            })
+            .sorted_by(|diff1, diff2| {
+                to_database_id(&diff1.meldung_id)
+                    .unwrap_or_default()
+                    .cmp(&to_database_id(&diff2.meldung_id).unwrap_or_default())
+            })
+            .collect_vec();
+
+        let different_content = content_diffs
+            .iter()
+            .map(|diff| diff.meldung_id.to_string())
+            .collect_vec();
+
+        ExportCheck {
+            db_entries: db_entries.len(),
+            db_meldungen: db_meldungen.len(),
+            xml_meldungen: xml_meldungen.len(),
+            missing_in_db,
+            missing_in_xml,
+            multiple_meldungen,
+            different_content,
+            content_diffs,
         }
+    }
 
-        content
+    pub fn has_discrepancies(&self) -> bool {
+        !self.missing_in_db.is_empty()
+            || !self.missing_in_xml.is_empty()
+            || !self.different_content.is_empty()
     }
 }
 
+fn first_word(value: &str) -> String {
+    value.split(' ').next().unwrap_or_default().to_string()
+}
+
+/// Element texts and attribute values of an XML document by local name in document order
+#[derive(Default)]
+struct XmlValues {
+    names: HashSet<String>,
+    elements: HashMap<String, Vec<String>>,
+    attributes: HashMap<(String, String), Vec<String>>,
+}
+
+impl XmlValues {
+    /// Collects the first text node of each element like `EXTRACTVALUE` and all attribute
+    /// values. Parsing stops at malformed XML.
+    fn parse(xml: &str) -> Self {
+        let mut values = XmlValues::default();
+        let mut reader = Reader::from_str(xml);
+        // Open elements and whether their first text node has been seen
+        let mut open = Vec::<(String, bool)>::new();
+
+        loop {
+            let text = match reader.read_event() {
+                Ok(Event::Start(ref element)) => {
+                    values.add_attributes(element);
+                    values.names.insert(local_name(element));
+                    open.push((local_name(element), false));
+                    continue;
+                }
+                Ok(Event::Empty(ref element)) => {
+                    values.add_attributes(element);
+                    values.names.insert(local_name(element));
+                    continue;
+                }
+                Ok(Event::End(_)) => {
+                    open.pop();
+                    continue;
+                }
+                Ok(Event::Text(text)) => match text.unescape() {
+                    Ok(text) => text.to_string(),
+                    Err(_) => break,
+                },
+                Ok(Event::CData(data)) => String::from_utf8_lossy(&data).to_string(),
+                Ok(Event::Eof) | Err(_) => break,
+                Ok(_) => continue,
+            };
+
+            if let Some((name, seen)) = open.last_mut() {
+                if !*seen {
+                    *seen = true;
+                    values
+                        .elements
+                        .entry(name.to_string())
+                        .or_default()
+                        .push(text.trim().to_string());
+                }
+            }
+        }
+
+        values
+    }
+
+    fn add_attributes(&mut self, element: &BytesStart) {
+        let name = local_name(element);
+        element
+            .attributes()
+            .filter_map(|attribute| attribute.ok())
+            .for_each(|attribute| {
+                self.attributes
+                    .entry((
+                        name.to_string(),
+                        String::from_utf8_lossy(attribute.key.local_name().as_ref()).to_string(),
+                    ))
+                    .or_default()
+                    .push(
+                        attribute
+                            .unescape_value()
+                            .map(|value| value.to_string())
+                            .unwrap_or_default(),
+                    );
+            });
+    }
+
+    /// Text content of all elements with given name separated by space like `EXTRACTVALUE`
+    fn element(&self, name: &str) -> String {
+        join_values(self.elements.get(name))
+    }
+
+    /// Values of an attribute of all elements with given name separated by space like
+    /// `EXTRACTVALUE`
+    fn attribute(&self, element: &str, attribute: &str) -> String {
+        join_values(
+            self.attributes
+                .get(&(element.to_string(), attribute.to_string())),
+        )
+    }
+
+    fn has_element(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+
+    fn has_element_containing(&self, part: &str) -> bool {
+        self.names.iter().any(|name| name.contains(part))
+    }
+
+    fn first_element(&self, name: &str) -> Option<String> {
+        self.elements
+            .get(name)
+            .and_then(|values| values.first().cloned())
+    }
+
+    fn first_attribute(&self, element: &str, attribute: &str) -> Option<String> {
+        self.attributes
+            .get(&(element.to_string(), attribute.to_string()))
+            .and_then(|values| values.first().cloned())
+    }
+}
+
+fn join_values(values: Option<&Vec<String>>) -> String {
+    values
+        .into_iter()
+        .flatten()
+        .filter(|value| !value.is_empty())
+        .join(" ")
+}
+
+fn local_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.local_name().as_ref()).to_string()
+}
+
+/// Unescaped value of the given attribute
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .attributes()
+        .filter_map(|attribute| attribute.ok())
+        .find(|attribute| attribute.key.local_name().as_ref() == name.as_bytes())
+        .and_then(|attribute| {
+            attribute
+                .unescape_value()
+                .ok()
+                .map(|value| value.to_string())
+        })
+}
+
 pub fn to_database_id(id: &str) -> Option<String> {
     let re1 = Regex::new(r"^(?<id>[0-9A-F]+)").unwrap();
     let re2 = Regex::new(r"(?<id>[0-9]+)$").unwrap();
@@ -165,7 +776,110 @@ pub fn to_database_id(id: &str) -> Option<String> {
 
 #[cfg(test)]
 mod tests {
-    use crate::lkrexport::{LkrExportProtocolFile, Meldung};
+    use std::path::Path;
+
+    use crate::filter::{DiagnosisPeriod, EntryRules};
+    use crate::lkrexport::{
+        ConditionIdScheme, ExportCheck, LkrExportError, LkrExportProtocolFile, Meldung,
+    };
+
+    #[test]
+    fn should_compose_condition_id() {
+        let default = ConditionIdScheme::default();
+        assert_eq!(
+            default.condition_id("20001234", "1"),
+            "d6ad167b59a87a3e92105964ee0b15a365c92397ec4b04cffecbb9a997754611"
+        );
+
+        let custom = ConditionIdScheme::new(
+            Some("https://fhir.example.org/identifiers/condition-id"),
+            Some("{system}|{patient_id}-{tumor_id}"),
+        )
+        .unwrap();
+        assert_ne!(
+            custom.condition_id("20001234", "1"),
+            default.condition_id("20001234", "1")
+        );
+        assert_eq!(
+            custom.condition_id("20001234", "1"),
+            ConditionIdScheme::new(
+                None,
+                Some("https://fhir.example.org/identifiers/condition-id|{patient_id}-{tumor_id}")
+            )
+            .unwrap()
+            .condition_id("20001234", "1")
+        );
+
+        assert!(ConditionIdScheme::new(None, Some("{patient}{tumor_id}")).is_err());
+        assert!(ConditionIdScheme::new(None, Some("{system}|{patient_id}")).is_err());
+    }
+
+    #[test]
+    fn should_extract_conditions_selected_by_rules() {
+        let meldung = |id: &str, tumor_id: &str, date: &str, meldende_stelle: &str| {
+            format!(
+                r#"<Meldung Meldung_ID="{}" Melder_ID="TEST">
+                    <Meldende_Stelle>{}</Meldende_Stelle>
+                    <Meldedatum>11.06.2024</Meldedatum>
+                    <Tumorzuordnung Tumor_ID="{}">
+                        <Primaertumor_ICD_Code>C17.1</Primaertumor_ICD_Code>
+                        <Primaertumor_ICD_Version>10 2015 GM</Primaertumor_ICD_Version>
+                        <Diagnosedatum>{}</Diagnosedatum>
+                    </Tumorzuordnung>
+                </Meldung>"#,
+                id, meldende_stelle, tumor_id, date
+            )
+        };
+        let protocol_file = LkrExportProtocolFile::parse(&format!(
+            r#"<ADT_GEKID Schema_Version="2.2.3"><Absender Absender_ID="TEST"/><Menge_Patient>
+                <Patient><Patienten_Stammdaten Patient_ID="20001234"/><Menge_Meldung>{}{}{}</Menge_Meldung></Patient>
+            </Menge_Patient></ADT_GEKID>"#,
+            meldung("TEST1", "1", "10.06.2024", "TEST"),
+            meldung("TEST2", "2", "10.06.2023", "TEST"),
+            meldung("TEST3", "3", "10.06.2024", "TEST9999"),
+        ))
+        .unwrap();
+        let rules = EntryRules::default();
+        let year = DiagnosisPeriod::Year("2024".into());
+
+        let actual = protocol_file.conditions(&year, &rules, false, true, false, false);
+
+        assert_eq!(actual.len(), 1);
+        assert_eq!(
+            actual[0].condition_id,
+            rules.condition_id.condition_id("20001234", "1")
+        );
+        assert_eq!(actual[0].icd_10_code, "C17.1");
+        assert_eq!(actual[0].diagnosis_date, "10.06.2024");
+        assert_eq!(actual[0].pat_id, Some("20001234".into()));
+
+        let actual = protocol_file.conditions(&year, &rules, false, false, true, false);
+
+        assert_eq!(actual.len(), 2);
+        assert_eq!(
+            actual
+                .iter()
+                .filter(|condition| condition.condition_id
+                    == rules.condition_id.condition_id("20001234", "3"))
+                .count(),
+            1
+        );
+
+        // Test data contains Meldungen of Tumorkonferenzen only
+        let testdaten =
+            LkrExportProtocolFile::parse(include_str!("../testdaten/testdaten_1.xml")).unwrap();
+        assert!(testdaten
+            .conditions(&year, &rules, false, false, true, true)
+            .is_empty());
+    }
+
+    #[test]
+    fn should_read_xml_files_of_directory() {
+        let actual = LkrExportProtocolFile::parse_path(Path::new("testdaten"));
+
+        assert!(actual.is_ok());
+        assert_eq!(actual.unwrap().patients.len(), 2);
+    }
 
     #[test]
     fn should_read_xml_file_content() {
@@ -175,6 +889,91 @@ mod tests {
         assert_eq!(actual.unwrap().patients.len(), 2);
     }
 
+    #[test]
+    fn should_return_error_on_missing_file_or_patients() {
+        let actual = LkrExportProtocolFile::parse_file(Path::new("testdaten/not_existing.xml"));
+
+        assert!(matches!(actual, Err(LkrExportError::Io(_, _))));
+        assert_eq!(
+            actual.err().unwrap().to_string(),
+            "Protokolldatei 'testdaten/not_existing.xml' nicht gefunden"
+        );
+
+        let actual = LkrExportProtocolFile::parse("<ADT_GEKID></ADT_GEKID>");
+
+        assert!(matches!(actual, Err(LkrExportError::NoPatients)));
+    }
+
+    #[test]
+    fn should_extract_values_like_extractvalue() {
+        let meldung = Meldung::new(
+            r#"<ADT_GEKID Schema_Version="2.2.3"><Meldung Meldung_ID="TEST1">
+                <Meldedatum>1.6.2024</Meldedatum>
+                <Diagnose Tumor_ID="1"><Primaertumor_ICD_Code>C17.1</Primaertumor_ICD_Code>
+                <Diagnosedatum>10.06.2024</Diagnosedatum></Diagnose>
+                <Tumorzuordnung Tumor_ID="1"><Diagnosedatum>00.06.2023</Diagnosedatum></Tumorzuordnung>
+            </Meldung></ADT_GEKID>"#,
+        );
+
+        assert_eq!(meldung.schema_version(), "2.2.3");
+        assert_eq!(meldung.tumor_id(), "1");
+        assert_eq!(meldung.primary_icd10_code(), "C17.1");
+        assert_eq!(meldung.diagnosis_date(), "10.06.2024");
+        assert_eq!(meldung.diagnosis_year(), "2024");
+        assert_eq!(
+            meldung.meldedatum(),
+            chrono::NaiveDate::from_ymd_opt(2024, 6, 1)
+        );
+        assert_eq!(meldung.patient_id(), "");
+    }
+
+    #[test]
+    fn should_parse_namespaces_cdata_comments_and_entities() {
+        let actual = LkrExportProtocolFile::parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <adt:ADT_GEKID xmlns:adt="http://www.gekid.de/namespace" Schema_Version="2.2.3">
+              <adt:Menge_Patient>
+                <adt:Patient Patient_Ref="1">
+                  <adt:Patienten_Stammdaten Patient_ID="2000&amp;1234"/>
+                  <!-- <Meldung Meldung_ID="COMMENT"></Meldung> -->
+                  <adt:Menge_Meldung>
+                    <adt:Meldung Meldung_ID="TEST&#49;">
+                      <adt:Tumorzuordnung Tumor_ID="1">
+                        <adt:Primaertumor_ICD_Code><![CDATA[C17.1]]></adt:Primaertumor_ICD_Code>
+                        <adt:Diagnosedatum><!-- unbekannt -->00.06.2024</adt:Diagnosedatum>
+                      </adt:Tumorzuordnung>
+                    </adt:Meldung>
+                  </adt:Menge_Meldung>
+                </adt:Patient>
+              </adt:Menge_Patient>
+            </adt:ADT_GEKID>"#,
+        );
+
+        assert!(actual.is_ok());
+
+        let patients = actual.unwrap().patients;
+
+        assert_eq!(patients.len(), 1);
+        assert_eq!(patients[0].patient_id, "2000&1234");
+        assert_eq!(patients[0].meldungen.len(), 1);
+
+        let meldung = &patients[0].meldungen[0];
+
+        assert_eq!(meldung.id(), Some("TEST1".to_string()));
+        assert_eq!(meldung.tumorzuordnung_id(), "1");
+        assert_eq!(meldung.icd10(), Some("C17.1".to_string()));
+        assert_eq!(meldung.diagnosis_date(), "00.06.2024");
+        assert!(meldung.raw_value.starts_with("<adt:Meldung "));
+        assert!(meldung.raw_value.ends_with("</adt:Meldung>"));
+    }
+
+    #[test]
+    fn should_return_error_on_malformed_xml() {
+        let actual = LkrExportProtocolFile::parse("<Patient><Meldung></Patient>");
+
+        assert!(matches!(actual, Err(LkrExportError::Xml(_))));
+    }
+
     #[test]
     fn should_get_meldungen() {
         let actual = LkrExportProtocolFile::parse(include_str!("../testdaten/testdaten_1.xml"));
@@ -183,8 +982,8 @@ mod tests {
 
         let patients = actual.unwrap().patients;
 
-        assert_eq!(patients[0].meldungen().len(), 1);
-        assert_eq!(patients[1].meldungen().len(), 1);
+        assert_eq!(patients[0].meldungen.len(), 1);
+        assert_eq!(patients[1].meldungen.len(), 1);
     }
 
     #[test]
@@ -196,11 +995,11 @@ mod tests {
         let patients = actual.unwrap().patients;
 
         assert_eq!(
-            patients[0].meldungen()[0].id(),
+            patients[0].meldungen[0].id(),
             Some("TEST1727528".to_string())
         );
         assert_eq!(
-            patients[1].meldungen()[0].id(),
+            patients[1].meldungen[0].id(),
             Some("001A5D50-TEST".to_string())
         );
     }
@@ -214,11 +1013,11 @@ mod tests {
         let patients = actual.unwrap().patients;
 
         assert_eq!(
-            patients[0].meldungen()[0].database_id(),
+            patients[0].meldungen[0].database_id(),
             Some("1727528".to_string())
         );
         assert_eq!(
-            patients[1].meldungen()[0].database_id(),
+            patients[1].meldungen[0].database_id(),
             Some("1727824".to_string())
         );
     }
@@ -231,40 +1030,90 @@ mod tests {
 
         let patients = actual.unwrap().patients;
 
-        assert_eq!(
-            patients[0].meldungen()[0].icd10(),
-            Some("C17.1".to_string())
+        assert_eq!(patients[0].meldungen[0].icd10(), Some("C17.1".to_string()));
+        assert_eq!(patients[1].meldungen[0].icd10(), Some("C17.2".to_string()));
+    }
+
+    #[test]
+    fn should_get_meldung_with_same_content_ignoring_margin() {
+        let meldung = Meldung::new("  <Test>\n  <Test2>TestInhalt 3</Test2>\n</Test>\n");
+
+        assert!(meldung.same_content(&Meldung::new("<Test><Test2>TestInhalt 3</Test2></Test>")));
+        assert!(!meldung.same_content(&Meldung::new("<Test><Test2>TestInhalt 4</Test2></Test>")));
+    }
+
+    #[test]
+    fn should_get_meldung_with_same_content_ignoring_self_closing_tags() {
+        let meldung = Meldung::new(
+            "  <Test>\n  <Test2/>\n  <Content>Test</Content>\n  <Test3/>\n  <Test2/>\n</Test>\n",
         );
-        assert_eq!(
-            patients[1].meldungen()[0].icd10(),
-            Some("C17.2".to_string())
+
+        assert!(meldung.same_content(&Meldung::new(
+            "<Test><Test2></Test2><Content>Test</Content><Test3></Test3><Test2></Test2></Test>"
+        )));
+    }
+
+    #[test]
+    fn should_get_meldung_with_same_content_ignoring_encoding() {
+        let meldung = Meldung::new(
+            r#"<Meldung Meldung_ID="M1" Melder_ID="A"><Text>Größe &gt; 2cm</Text></Meldung>"#,
         );
+
+        assert!(meldung.same_content(&Meldung::new(
+            r#"<adt:Meldung xmlns:adt="http://www.gekid.de/namespace" Melder_ID="A" Meldung_ID="M1"><adt:Text>Gr&#246;&#xDF;e &#62; 2cm</adt:Text></adt:Meldung>"#
+        )));
     }
 
     #[test]
-    fn should_get_meldung_with_trimmed_margin() {
-        let meldung = Meldung {
-            raw_value: "  <Test>\n  <Test2>TestInhalt 3</Test2>\n</Test>\n".into(),
-        };
+    fn should_check_export_against_protocol_file() {
+        let protocol_file =
+            LkrExportProtocolFile::parse(include_str!("../testdaten/testdaten_1.xml")).unwrap();
 
-        assert_eq!(
-            meldung.sanitized_xml_string(),
-            "<Test><Test2>TestInhalt 3</Test2></Test>".to_string()
-        );
+        let db_entries = protocol_file
+            .patients
+            .iter()
+            .enumerate()
+            .map(|(idx, patient)| {
+                (
+                    idx.to_string(),
+                    format!("<Patient>{}</Patient>", patient.meldungen[0].raw_value),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let actual = ExportCheck::new(&db_entries[0..1], &protocol_file);
+
+        assert_eq!(actual.db_entries, 1);
+        assert_eq!(actual.db_meldungen, 1);
+        assert_eq!(actual.xml_meldungen, 2);
+        assert_eq!(actual.missing_in_db, vec!["001A5D50-TEST".to_string()]);
+        assert!(actual.missing_in_xml.is_empty());
+        assert!(actual.different_content.is_empty());
+        assert!(actual.has_discrepancies());
     }
 
     #[test]
-    fn should_get_meldung_without_self_closing_tags() {
-        let meldung = Meldung {
-            raw_value:
-                "  <Test>\n  <Test2/>\n  <Content>Test</Content>\n  <Test3/>\n  <Test2/>\n</Test>\n"
-                    .into(),
-        };
+    fn should_get_changes_of_different_content() {
+        let protocol_file = LkrExportProtocolFile::parse(
+            r#"<Patient><Meldung Meldung_ID="M1"><Diagnose><Primaertumor_ICD_Code>C17.1</Primaertumor_ICD_Code></Diagnose></Meldung></Patient>"#,
+        )
+        .unwrap();
+        let db_entries = vec![(
+            "1".to_string(),
+            r#"<Patient><Meldung Meldung_ID="M1"><Diagnose><Primaertumor_ICD_Code>C17.2</Primaertumor_ICD_Code></Diagnose></Meldung></Patient>"#
+                .to_string(),
+        )];
+
+        let actual = ExportCheck::new(&db_entries, &protocol_file);
 
+        assert_eq!(actual.different_content, vec!["M1".to_string()]);
+        let changes = actual.content_diffs[0].changes().unwrap();
+        assert_eq!(changes.len(), 1);
         assert_eq!(
-            meldung.sanitized_xml_string(),
-            "<Test><Test2></Test2><Content>Test</Content><Test3></Test3><Test2></Test2></Test>"
-                .to_string()
+            changes[0].path,
+            "/Meldung/Diagnose/Primaertumor_ICD_Code/text()"
         );
+        assert_eq!(changes[0].old, Some("C17.1".to_string()));
+        assert_eq!(changes[0].new, Some("C17.2".to_string()));
     }
 }
@@ -18,25 +18,54 @@
  * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
  */
 
-use std::collections::HashMap;
 use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
 
+use chrono::{Local, NaiveDate};
 use clap::Parser;
 use console::{style, Term};
 use csv::WriterBuilder;
 use itertools::Itertools;
-
-use crate::cli::{Cli, SubCommand};
-use crate::common::{Check, DiffRecord, Icd10GroupSize};
+use serde::Serialize;
+
+use crate::baseline::{Baseline, BaselineChange};
+use crate::cli::{Cli, DatabaseArgs, DiagnosisArgs, OutputFormat, SnapshotCommand, SubCommand};
+use crate::common::{
+    Check, ConditionDiffs, Difference, ExportData, Icd10GroupSize, Icd10GroupSizeDiff,
+    Icd10GroupTrend,
+};
+use crate::config::{Config, Filter};
 use crate::database::DatabaseSource;
-use crate::lkrexport::{to_database_id, LkrExportProtocolFile, Meldung};
-
+use crate::filter::{DiagnosisPeriod, Exclusion, MeldendeStelleSize};
+use crate::icd_groups::{IcdGroupVersions, IcdGroups, OTHER};
+use crate::lkrexport::{to_database_id, ContentDiff, ExportCheck, LkrExportProtocolFile};
+use crate::output::{
+    BaselineResult, CheckExportResult, CompareCountsResult, CompareCsvRecord, CompareResult,
+    DiffCsvRecord, ExplainResult, ExportResult, GroupSizes, GroupTrend, JsonOutput, Parameters,
+    ReportResult, TrendResult,
+};
+use crate::report::Report;
+use crate::snapshot::{Snapshot, SnapshotHeader, SnapshotWriter};
+use crate::source::Source;
+
+mod baseline;
+mod canonical;
 mod cli;
 mod common;
+mod compare;
+mod config;
 mod database;
+mod filter;
+mod icd_groups;
 mod lkrexport;
 mod opal;
+mod output;
+mod report;
 mod resources;
+mod snapshot;
+mod source;
 
 fn request_password_if_none(password: Option<String>) -> String {
     if let Some(password) = password {
@@ -59,12 +88,53 @@ fn sanitize_year(year: &str) -> String {
     }
 }
 
-fn print_items(items: &[Icd10GroupSize]) {
+/// Diagnosis periods given by years or by a date range. Each period is printed in its own block.
+fn diagnosis_periods(args: &DiagnosisArgs) -> Result<Vec<DiagnosisPeriod>, String> {
+    let parse = |value: &str| {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map_err(|_| format!("Ungültiges Datum '{}'", value))
+    };
+
+    match (&args.diagnosis_from, &args.diagnosis_to) {
+        (Some(from), Some(to)) => {
+            let (from, to) = (parse(from)?, parse(to)?);
+            if from > to {
+                return Err(format!(
+                    "Diagnosedatum ab {} liegt nach Diagnosedatum bis {}",
+                    from, to
+                ));
+            }
+            Ok(vec![DiagnosisPeriod::Range(from, to)])
+        }
+        _ => Ok(args
+            .year
+            .iter()
+            .map(|year| DiagnosisPeriod::Year(sanitize_year(year.trim())))
+            .unique()
+            .collect()),
+    }
+}
+
+fn print_period_header(period: &DiagnosisPeriod, periods: usize) {
+    if periods > 1 {
+        let _ = Term::stdout().write_line(
+            &style(format!("Ergebnis für {}", period))
+                .bold()
+                .underlined()
+                .to_string(),
+        );
+    }
+}
+
+fn print_items(items: &[Icd10GroupSize], icd_groups: &IcdGroups) {
     let term = Term::stdout();
     let _ = term.write_line(
-        &style("Anzahl der Conditions nach ICD-10-Gruppe")
-            .yellow()
-            .to_string(),
+        &style(format!(
+            "Anzahl der Conditions nach ICD-10-Gruppe (Version {})",
+            icd_groups.version
+        ))
+        .yellow()
+        .to_string(),
     );
     items.iter().for_each(|item| {
         let _ = term.write_line(&format!(
@@ -74,27 +144,595 @@ fn print_items(items: &[Icd10GroupSize]) {
             item.size
         ));
     });
-    let sum: usize = items
-        .iter()
-        .filter(|item| item.name != "Other")
-        .map(|item| item.size)
-        .sum();
+    let sizes = GroupSizes::new(items);
     let _ = term.write_line(&style("─".repeat(35)).dim().to_string());
     let _ = term.write_line(
         &style(format!(
             "{:<20} {:<6} ={:>6}",
-            "Summe (C**.*/D**.*)", "", sum
+            "Summe (C**.*/D**.*)", "", sizes.sum
+        ))
+        .dim()
+        .to_string(),
+    );
+    let _ = term.write_line(
+        &style(format!(
+            "{:<20} {:<6} ={:>6}",
+            "Gesamtsumme", "", sizes.total
+        ))
+        .dim()
+        .to_string(),
+    );
+    let _ = term.write_line(&style("─".repeat(35)).dim().to_string());
+}
+
+fn print_size_diffs(items: &[Icd10GroupSizeDiff], tolerance: f64, icd_groups: &IcdGroups) {
+    let term = Term::stdout();
+    let _ = term.write_line(
+        &style(format!(
+            "Anzahl der Conditions nach ICD-10-Gruppe (Version {}) in CSV-Datei und Datenbank",
+            icd_groups.version
+        ))
+        .yellow()
+        .to_string(),
+    );
+    let _ = term.write_line(&format!(
+        "{:<20} {:>8} {:>8} {:>8} {:>9}",
+        "ICD-10-Gruppe", "CSV", "DB", "Diff", "Diff (%)"
+    ));
+    items.iter().for_each(|item| {
+        let line = format!(
+            "{:<20} {:>8} {:>8} {:>+8} {:>9}",
+            item.name,
+            item.csv_size,
+            item.db_size,
+            item.delta(),
+            match item.relative_delta() {
+                Some(relative_delta) => format!("{:+.2}", relative_delta),
+                None => "-".to_string(),
+            }
+        );
+        let _ = term.write_line(&match item.exceeds(tolerance) {
+            true => style(line).bold().red().to_string(),
+            false => line,
+        });
+    });
+    let _ = term.write_line(&style("─".repeat(56)).dim().to_string());
+    let _ = term.write_line(
+        &style(format!(
+            "{} von {} ICD-10-Gruppen mit Abweichung über {}%",
+            items.iter().filter(|item| item.exceeds(tolerance)).count(),
+            items.len(),
+            tolerance
         ))
         .dim()
         .to_string(),
     );
-    let sum: usize = items.iter().map(|item| item.size).sum();
+}
+
+/// Prints conditions which differ between `source` like "der Datenbank" and the CSV file
+fn print_condition_diffs(
+    condition_diffs: &ConditionDiffs,
+    source: &str,
+    source_column: &str,
+    file: &Path,
+    icd_groups: &IcdGroups,
+) {
+    let term = Term::stdout();
+
+    let _ = term.write_line(
+        &style(format!(
+            "{} Conditions aus {} - aber nicht in Datei '{}'",
+            condition_diffs.not_in_csv.len(),
+            source,
+            file.to_str().unwrap_or_default()
+        ))
+        .green()
+        .to_string(),
+    );
+
+    let _ = term.write_line(&format!(
+        "{:<64}   {:<10}   {:<5}   {:<5}   {}",
+        "Condition-ID", "Datum", "ICD10", "", "PAT-ID"
+    ));
+
+    condition_diffs.not_in_csv.iter().for_each(|&item| {
+        let _ = term.write_line(&format!(
+            "{:<64}   {:<10}   {:<5}   {:<5}   {}",
+            item.condition_id,
+            item.diagnosis_date,
+            match Check::is_relevant(&item.icd_10_code, icd_groups) {
+                true => style(format!("{:<5}", item.icd_10_code)).bold().red(),
+                _ => style(format!("{:<5}", item.icd_10_code)),
+            },
+            "",
+            item.pat_id.as_deref().unwrap_or_default()
+        ));
+    });
+
+    let _ = term.write_line(
+        &style(format!(
+            "{} Conditions aus Datei '{}' - aber nicht in {}",
+            condition_diffs.not_in_db.len(),
+            file.to_str().unwrap_or_default(),
+            source
+        ))
+        .green()
+        .to_string(),
+    );
+
+    let _ = term.write_line(&format!(
+        "{:<64}   {:<10}   {:<5}",
+        "Condition-ID", "Datum", "ICD10"
+    ));
+
+    condition_diffs.not_in_db.iter().for_each(|&item| {
+        let _ = term.write_line(&format!(
+            "{:<64}   {:<10}   {:<5}",
+            item.condition_id,
+            item.diagnosis_date,
+            match Check::is_relevant(&item.icd_10_code, icd_groups) {
+                true => style(format!("{:<5}", item.icd_10_code)).bold().red(),
+                _ => style(format!("{:<5}", item.icd_10_code)),
+            }
+        ));
+    });
+
+    let _ = term.write_line(
+        &style(format!(
+            "{} Conditions mit abweichenden Angaben, davon {} nur mit ICD10-Code in gleicher ICD-10-Gruppe",
+            condition_diffs.changed.len(),
+            condition_diffs.same_group_only()
+        ))
+        .green()
+        .to_string(),
+    );
+
+    let _ = term.write_line(&format!(
+        "{:<64}   {:<13}   {:<10}   {:<10}   {}",
+        "Condition-ID", "Abweichung", "CSV", source_column, "PAT-ID"
+    ));
+
+    condition_diffs.changed.iter().for_each(|item| {
+        item.changed_values().into_iter().enumerate().for_each(
+            |(index, (difference, csv_value, db_value))| {
+                let value = |value: &str| match difference {
+                    Difference::Icd10Code | Difference::Icd10Group
+                        if Check::is_relevant(value, icd_groups) =>
+                    {
+                        style(format!("{:<10}", value)).bold().red()
+                    }
+                    _ => style(format!("{:<10}", value)),
+                };
+                let _ = term.write_line(&format!(
+                    "{:<64}   {:<13}   {}   {}   {}",
+                    match index {
+                        0 => item.condition_id.as_str(),
+                        _ => "",
+                    },
+                    difference.to_string(),
+                    value(csv_value),
+                    value(db_value),
+                    match index {
+                        0 => item.pat_id().unwrap_or_default(),
+                        _ => "",
+                    }
+                ));
+            },
+        );
+    });
+
+    if condition_diffs.suppressed > 0 {
+        let _ = term.write_line(
+            &style(format!(
+                "{} bekannte Abweichungen laut Baseline ausgeblendet",
+                condition_diffs.suppressed
+            ))
+            .yellow()
+            .to_string(),
+        );
+    }
+}
+
+/// CSV items compared for the given period. A single diagnosis year uses the whole file as
+/// before. Otherwise only items with a date within the period are compared, items with unknown
+/// date format are kept.
+fn period_csv_items(
+    all_csv_items: &[ExportData],
+    period: &DiagnosisPeriod,
+    periods: usize,
+) -> Vec<ExportData> {
+    match (periods, period) {
+        (1, DiagnosisPeriod::Year(_)) => all_csv_items.to_vec(),
+        _ => all_csv_items
+            .iter()
+            .filter(|item| period.contains_date(&item.diagnosis_date) != Some(false))
+            .cloned()
+            .collect_vec(),
+    }
+}
+
+/// Writes conditions which differ into CSV files within the given directory
+fn write_condition_diffs(
+    output_dir: &Path,
+    condition_diffs: &ConditionDiffs,
+    xls_csv: bool,
+    icd_groups: &IcdGroups,
+) -> Result<(), String> {
+    fs::create_dir_all(output_dir)
+        .map_err(|err| format!("Kann Ausgabeverzeichnis nicht erstellen: {}", err))?;
+
+    write_csv(
+        &output_dir.join("not_in_csv.csv"),
+        &condition_diffs
+            .not_in_csv
+            .iter()
+            .map(|&item| CompareCsvRecord::new(item, icd_groups))
+            .collect_vec(),
+        xls_csv,
+    )
+    .and_then(|_| {
+        write_csv(
+            &output_dir.join("not_in_db.csv"),
+            &condition_diffs
+                .not_in_db
+                .iter()
+                .map(|&item| CompareCsvRecord::new(item, icd_groups))
+                .collect_vec(),
+            xls_csv,
+        )
+    })
+    .and_then(|_| {
+        write_csv(
+            &output_dir.join("icd10_diff.csv"),
+            &condition_diffs
+                .changed
+                .iter()
+                .map(|item| DiffCsvRecord::new(item, icd_groups))
+                .collect_vec(),
+            xls_csv,
+        )
+    })
+    .map_err(|err| format!("Kann Ausgabedatei nicht schreiben: {}", err))
+}
+
+/// Consecutive diagnosis years from first to last year, both included
+fn diagnosis_years(from_year: &str, to_year: &str) -> Result<Vec<String>, String> {
+    let parse = |year: &str| {
+        sanitize_year(year)
+            .parse::<u16>()
+            .map_err(|_| format!("Ungültiges Diagnosejahr '{}'", year))
+    };
+
+    let (from_year, to_year) = (parse(from_year)?, parse(to_year)?);
+    if from_year > to_year {
+        return Err(format!(
+            "Erstes Diagnosejahr {} liegt nach letztem Diagnosejahr {}",
+            from_year, to_year
+        ));
+    }
+
+    Ok((from_year..=to_year).map(|year| year.to_string()).collect())
+}
+
+fn print_trend(
+    years: &[String],
+    items: &[Icd10GroupTrend],
+    sum: &Icd10GroupTrend,
+    total: &Icd10GroupTrend,
+    highlight: f64,
+    icd_groups: &IcdGroups,
+) {
+    let term = Term::stdout();
+    let width = 20 + years.len() * 15;
+
+    let line = |item: &Icd10GroupTrend| {
+        item.sizes.iter().zip(item.relative_changes()).fold(
+            format!("{:<20}", item.name),
+            |line, (size, change)| {
+                let change = match change {
+                    Some(change) if change.abs() >= highlight => {
+                        style(format!("{:>+6.0}%", change)).bold().red().to_string()
+                    }
+                    Some(change) => format!("{:>+6.0}%", change),
+                    None => format!("{:>7}", ""),
+                };
+                format!("{} {:>6} {}", line, size, change)
+            },
+        )
+    };
+
+    let _ = term.write_line(
+        &style(format!(
+            "Anzahl der Conditions nach ICD-10-Gruppe (Version {}) und Diagnosejahr",
+            icd_groups.version
+        ))
+        .yellow()
+        .to_string(),
+    );
+    let _ = term.write_line(
+        &years
+            .iter()
+            .fold(format!("{:<20}", "ICD-10-Gruppe"), |line, year| {
+                format!("{} {:>6} {:>7}", line, year, "Δ")
+            }),
+    );
+    items.iter().for_each(|item| {
+        let _ = term.write_line(&line(item));
+    });
+    let _ = term.write_line(&style("─".repeat(width)).dim().to_string());
+    let _ = term.write_line(&style(line(sum)).dim().to_string());
+    let _ = term.write_line(&style(line(total)).dim().to_string());
+    let _ = term.write_line(&style("─".repeat(width)).dim().to_string());
+    let _ = term.write_line(
+        &style(format!(
+            "Änderungen zum Vorjahr ab {}% sind hervorgehoben",
+            highlight
+        ))
+        .dim()
+        .to_string(),
+    );
+}
+
+fn print_meldende_stellen(items: &[MeldendeStelleSize], include_extern: bool) {
+    let term = Term::stdout();
     let _ = term.write_line(
-        &style(format!("{:<20} {:<6} ={:>6}", "Gesamtsumme", "", sum))
-            .dim()
+        &style("Anzahl der Conditions nach Meldender Stelle")
+            .yellow()
             .to_string(),
     );
+    items.iter().for_each(|item| {
+        let _ = term.write_line(&format!(
+            "{:<20} {:<6} ={:>6}",
+            match item.name.as_str() {
+                "" => "(ohne Angabe)",
+                name => name,
+            },
+            match item.is_extern {
+                true => "extern",
+                false => "",
+            },
+            item.size
+        ));
+    });
     let _ = term.write_line(&style("─".repeat(35)).dim().to_string());
+    if !include_extern && items.iter().any(|item| item.is_extern) {
+        let _ = term.write_line(
+            &style("Externe Meldende Stellen sind nicht in den Kennzahlen enthalten")
+                .dim()
+                .to_string(),
+        );
+    }
+}
+
+fn reasons(exclusion: &Exclusion) -> String {
+    exclusion
+        .reasons
+        .iter()
+        .map(|reason| reason.to_string())
+        .join("; ")
+}
+
+fn print_exclusions(exclusions: &[Exclusion]) {
+    let term = Term::stdout();
+    let _ = term.write_line(
+        &style(format!(
+            "{} nicht berücksichtigte Meldungen",
+            exclusions.len()
+        ))
+        .yellow()
+        .to_string(),
+    );
+    let _ = term.write_line(&format!(
+        "{:<64}   {:>7}   {:<5}   {}",
+        "Condition-ID", "Version", "ICD10", "Grund"
+    ));
+    exclusions.iter().for_each(|exclusion| {
+        let _ = term.write_line(&format!(
+            "{:<64}   {:>7}   {:<5}   {}",
+            exclusion.condition_id,
+            exclusion
+                .versionsnummer
+                .map(|version| version.to_string())
+                .unwrap_or_default(),
+            exclusion.icd10_code,
+            reasons(exclusion)
+        ));
+    });
+}
+
+fn print_explanation(condition_id: &str, meldungen: &[Exclusion], selected: bool) {
+    let term = Term::stdout();
+    let _ = term.write_line(
+        &style(format!("Meldungen zur Condition {}", condition_id))
+            .yellow()
+            .to_string(),
+    );
+    let _ = term.write_line(&format!(
+        "{:>8}   {:<24}   {:>7}   {:<5}   {:<10}   {}",
+        "ID", "Meldung-ID", "Version", "ICD10", "Datum", "Ergebnis"
+    ));
+    meldungen.iter().for_each(|meldung| {
+        let _ = term.write_line(&format!(
+            "{:>8}   {:<24}   {:>7}   {:<5}   {:<10}   {}",
+            meldung.id,
+            meldung.meldung_id.as_deref().unwrap_or_default(),
+            meldung
+                .versionsnummer
+                .map(|version| version.to_string())
+                .unwrap_or_default(),
+            meldung.icd10_code,
+            meldung.diagnosis_date,
+            match meldung.is_selected() {
+                true => style("berücksichtigt".to_string()).green(),
+                false => style(reasons(meldung)).red(),
+            }
+        ));
+    });
+
+    let _ = term.write_line(&match (meldungen.is_empty(), selected) {
+        (true, _) => style("Keine Meldungen zu dieser Condition gefunden".to_string())
+            .bold()
+            .red()
+            .to_string(),
+        (false, true) => style("Die Condition ist in Export und Abgleich enthalten".to_string())
+            .green()
+            .to_string(),
+        (false, false) => {
+            style("Die Condition ist nicht in Export und Abgleich enthalten".to_string())
+                .bold()
+                .red()
+                .to_string()
+        }
+    });
+}
+
+fn print_export_check(export_check: &ExportCheck, show_diff: bool) {
+    let term = Term::stdout();
+
+    let _ = term.write_line(
+        &style(format!(
+            "{} Datenbankeinträge mit {} Meldungen abgerufen",
+            export_check.db_entries, export_check.db_meldungen
+        ))
+        .green()
+        .to_string(),
+    );
+
+    fn print_missing_ids(missing_ids: &[String], term: &Term) {
+        missing_ids.iter().for_each(|item| {
+            let _ = term.write_line(&format!(
+                "{} ({})",
+                item,
+                to_database_id(item).unwrap_or("?".into())
+            ));
+        });
+    }
+
+    if export_check.db_meldungen != export_check.xml_meldungen {
+        let _ = term.write_line(
+            &style("\nNicht übereinstimmende Anzahl an Meldungen:")
+                .yellow()
+                .to_string(),
+        );
+        let _ = term.write_line(&format!(
+            "Datenbank:      {:>10}\nProtokolldatei: {:>10}",
+            export_check.db_meldungen, export_check.xml_meldungen
+        ));
+
+        if !export_check.missing_in_db.is_empty() {
+            let _ = term.write_line(
+                &style("\nIn der Datenbank fehlende Meldungen:")
+                    .yellow()
+                    .to_string(),
+            );
+
+            print_missing_ids(&export_check.missing_in_db, &term);
+        }
+
+        if !export_check.missing_in_xml.is_empty() {
+            let _ = term.write_line(
+                &style("\nIn der Protokolldatei fehlende Meldungen:")
+                    .yellow()
+                    .to_string(),
+            );
+
+            print_missing_ids(&export_check.missing_in_xml, &term);
+        }
+    }
+
+    if !export_check.multiple_meldungen.is_empty() {
+        let _ = term.write_line(
+            &style("\nFolgende Einträge in `lkr_meldung_export` haben mehrere Meldungsinhalte in `xml_daten`:")
+                .yellow()
+                .to_string(),
+        );
+
+        export_check.multiple_meldungen.iter().for_each(|item| {
+            let _ = term.write_line(item);
+        });
+    }
+
+    if !export_check.different_content.is_empty() {
+        let _ = term.write_line(
+            &style(&format!(
+                "\nFolgende {} Meldungen unterscheiden sich in der Datenbank und der Protokolldatei:",
+                export_check.different_content.len()
+            ))
+            .yellow()
+            .to_string(),
+        );
+
+        export_check.content_diffs.iter().for_each(|diff| {
+            let _ = term.write_line(&format!(
+                "{} ({})",
+                diff.meldung_id,
+                to_database_id(&diff.meldung_id).unwrap_or("?".into())
+            ));
+            if show_diff {
+                print_content_changes(diff, &term);
+            }
+        });
+    }
+}
+
+/// Prints changes from the protocol file to the database with XPath, old and new value
+fn print_content_changes(diff: &ContentDiff, term: &Term) {
+    match diff.changes() {
+        Ok(changes) => changes.iter().for_each(|change| {
+            let _ = term.write_line(&format!(
+                "  {} ({}): {} => {}",
+                change.path,
+                change.kind,
+                style(change.old.as_deref().unwrap_or("-")).red(),
+                style(change.new.as_deref().unwrap_or("-")).green()
+            ));
+        }),
+        Err(err) => {
+            let _ = term.write_line(&format!(
+                "  {}",
+                style(format!("Unterschiede nicht ermittelbar: {}", err)).red()
+            ));
+        }
+    }
+}
+
+/// Writes both versions of each differing Meldung in canonical form, if possible, into the
+/// given directory
+fn write_content_diffs(diff_dir: &Path, content_diffs: &[ContentDiff]) -> Result<(), String> {
+    fs::create_dir_all(diff_dir)
+        .map_err(|err| format!("Kann Ausgabeverzeichnis nicht erstellen: {}", err))?;
+
+    content_diffs.iter().try_for_each(|diff| {
+        let (protocol_xml, database_xml) = match diff.canonical() {
+            Ok((protocol, database)) => (
+                protocol.to_pretty_xml_string(),
+                database.to_pretty_xml_string(),
+            ),
+            Err(_) => (diff.protocol_xml.to_string(), diff.database_xml.to_string()),
+        };
+        let name = diff
+            .meldung_id
+            .chars()
+            .map(
+                |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    true => c,
+                    false => '_',
+                },
+            )
+            .collect::<String>();
+
+        fs::write(
+            diff_dir.join(format!("{}.protokoll.xml", name)),
+            protocol_xml,
+        )
+        .and_then(|_| {
+            fs::write(
+                diff_dir.join(format!("{}.datenbank.xml", name)),
+                database_xml,
+            )
+        })
+        .map_err(|err| format!("Kann Ausgabedatei nicht schreiben: {}", err))
+    })
 }
 
 fn print_extern_notice(include_extern: bool) {
@@ -111,518 +749,1221 @@ fn print_extern_notice(include_extern: bool) {
     );
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let term = Term::stdout();
+fn print_snapshot_notice(header: &SnapshotHeader) {
+    let _ = Term::stdout().write_line(
+        format!(
+            "{} Verwende Snapshot der Datenbank '{}' vom {} statt Datenbankverbindung.",
+            style("Hinweis:").bold().underlined(),
+            header.database,
+            header.created_at
+        )
+        .as_str(),
+    );
+}
 
-    match Cli::parse().cmd {
-        SubCommand::OpalFile { file } => {
-            let items =
-                opal::OpalCsvFile::check(file.as_path()).map_err(|_e| "Kann Datei nicht lesen")?;
+fn print_wait_message(output_format: OutputFormat, message: &str) {
+    if output_format == OutputFormat::Text {
+        let _ = Term::stdout().write_line(&style(message).blue().bright().to_string());
+    }
+}
+
+fn clear_wait_message(output_format: OutputFormat) {
+    if output_format == OutputFormat::Text {
+        let _ = Term::stdout().clear_last_lines(1);
+    }
+}
+
+fn write_csv<T: Serialize>(path: &Path, items: &[T], xls_csv: bool) -> Result<(), csv::Error> {
+    let writer_builder = &mut WriterBuilder::new();
+    let mut writer_builder = writer_builder.has_headers(true);
+    if xls_csv {
+        writer_builder = writer_builder.delimiter(b';');
+    }
+    let mut writer = writer_builder.from_path(path)?;
+
+    for item in items {
+        writer.serialize(item)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn print_json<T: Serialize>(output: &JsonOutput<T>) {
+    let _ = Term::stdout().write_line(&output.to_json_string());
+}
+
+/// Prints documents, one per diagnosis period, as array even if there is only one period
+fn print_json_blocks(blocks: Vec<serde_json::Value>) {
+    let _ = Term::stdout().write_line(
+        &serde_json::to_string_pretty(&serde_json::Value::Array(blocks)).unwrap_or_default(),
+    );
+}
+
+/// Result of a successful run, used to determine the exit code
+#[derive(PartialEq)]
+enum Outcome {
+    /// Exit code 0: No discrepancies found
+    Consistent,
+    /// Exit code 1: Discrepancies found
+    Discrepancies,
+}
+
+impl Outcome {
+    fn from_discrepancies(discrepancies: bool) -> Self {
+        match discrepancies {
+            true => Outcome::Discrepancies,
+            false => Outcome::Consistent,
+        }
+    }
+}
+
+/// Exit code 2: Technical error, e.g. unreadable file or database access failed
+const EXIT_CODE_ERROR: u8 = 2;
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(Outcome::Consistent) => ExitCode::SUCCESS,
+        Ok(Outcome::Discrepancies) => ExitCode::FAILURE,
+        Err(err) => {
+            let _ =
+                Term::stderr().write_line(&format!("{} {}", style("Fehler:").red().bold(), err));
+            ExitCode::from(EXIT_CODE_ERROR)
+        }
+    }
+}
+
+fn database_source(
+    config: &Config,
+    args: &DatabaseArgs,
+    profile: Option<&str>,
+) -> Result<DatabaseSource, Box<dyn Error>> {
+    let settings = config.connection_settings(args, profile)?;
+    let password = request_password_if_none(settings.password.clone());
+
+    Ok(DatabaseSource::connect(&settings, &password)?)
+}
 
-            print_items(&items);
+/// Uses the snapshot file, if given, instead of a database connection
+fn source(
+    config: &Config,
+    args: &DatabaseArgs,
+    snapshot: Option<&Path>,
+    profile: Option<&str>,
+    output_format: OutputFormat,
+) -> Result<Source, Box<dyn Error>> {
+    match snapshot {
+        Some(snapshot) => {
+            let snapshot = Snapshot::open(snapshot)?;
+            if output_format == OutputFormat::Text {
+                print_snapshot_notice(&snapshot.header);
+            }
+            Ok(Source::snapshot(snapshot, config.entry_rules()?))
+        }
+        None => Ok(Source::database(
+            database_source(config, args, profile)?,
+            config.entry_rules()?,
+        )),
+    }
+}
+
+fn run(cli: Cli) -> Result<Outcome, Box<dyn Error>> {
+    let term = Term::stdout();
+    let output_format = cli.output_format;
+    let config = Config::load(cli.config.as_deref())?;
+    let icd_group_versions = IcdGroupVersions::load(cli.icd_groups.as_deref())?;
+    let grouping_version = cli.grouping_version;
+    let icd_groups = icd_group_versions.select(
+        grouping_version.as_deref(),
+        cli.cmd.year().map(sanitize_year).as_deref(),
+    )?;
+    let profile = cli.profile;
+    let mut outcome = Outcome::Consistent;
+
+    match cli.cmd {
+        SubCommand::OpalFile { file } => {
+            let items = opal::OpalCsvFile::check(file.as_path(), &icd_groups)?;
+
+            match output_format {
+                OutputFormat::Text => print_items(&items, &icd_groups),
+                OutputFormat::Json => print_json(&JsonOutput::new(
+                    "opal-file",
+                    &icd_groups,
+                    Parameters {
+                        file: file.to_str().map(|file| file.to_string()),
+                        ..Default::default()
+                    },
+                    GroupSizes::new(&items),
+                )),
+            }
         }
         SubCommand::Database {
             database,
-            host,
-            password,
-            port,
-            user,
-            year,
-            ignore_exports_since,
-            ignore_non_obds_2,
-            include_extern,
-            include_histo_zyto,
+            snapshot,
+            diagnosis,
+            filter,
             schema_versions,
+            show_excluded,
         } => {
-            let password = request_password_if_none(password);
-            let year = sanitize_year(&year);
-
-            let _ = term.write_line(
-                &style(format!("Warte auf Daten für das Diagnosejahr {}...", year))
-                    .blue()
-                    .bright()
-                    .to_string(),
-            );
-
-            let db = DatabaseSource::new(&database, &host, &password, port, &user);
-            let items = db
-                .check(
-                    &year,
-                    &ignore_exports_since.unwrap_or("9999-12-31".into()),
+            let periods = diagnosis_periods(&diagnosis)?;
+            let mut db = source(
+                &config,
+                &database,
+                snapshot.as_deref(),
+                profile.as_deref(),
+                output_format,
+            )?;
+            let Filter {
+                ignore_exports_since,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            } = config.filter(&filter);
+
+            let mut blocks = vec![];
+            for period in &periods {
+                let icd_groups =
+                    icd_group_versions.select_for_period(grouping_version.as_deref(), period)?;
+
+                print_wait_message(output_format, &format!("Warte auf Daten für {}...", period));
+
+                let items = db.check(
+                    period,
+                    ignore_exports_since.as_deref().unwrap_or("9999-12-31"),
                     ignore_non_obds_2,
                     include_extern,
                     include_histo_zyto,
                     schema_versions,
-                )
-                .map_err(|_e| "Fehler bei Zugriff auf die Datenbank")?;
-
-            let _ = term.clear_last_lines(1);
+                    &icd_groups,
+                )?;
+                // Always including external Meldungen to show the result of the extern rule
+                let meldende_stellen = db.meldende_stellen(
+                    period,
+                    ignore_exports_since.as_deref().unwrap_or("9999-12-31"),
+                    ignore_non_obds_2,
+                    true,
+                    include_histo_zyto,
+                )?;
+                let excluded = match show_excluded {
+                    true => Some(db.excluded(
+                        period,
+                        ignore_exports_since.as_deref().unwrap_or("9999-12-31"),
+                        ignore_non_obds_2,
+                        include_extern,
+                        include_histo_zyto,
+                    )?),
+                    false => None,
+                };
+
+                clear_wait_message(output_format);
+
+                match output_format {
+                    OutputFormat::Text => {
+                        print_period_header(period, periods.len());
+                        print_items(&items, &icd_groups);
+                        print_meldende_stellen(&meldende_stellen, include_extern);
+                        if let Some(ref excluded) = excluded {
+                            print_exclusions(excluded);
+                        }
+                    }
+                    OutputFormat::Json => blocks.push(
+                        JsonOutput::new(
+                            "database",
+                            &icd_groups,
+                            Parameters {
+                                snapshot: snapshot
+                                    .as_ref()
+                                    .and_then(|snapshot| snapshot.to_str())
+                                    .map(|snapshot| snapshot.to_string()),
+                                ignore_exports_since: ignore_exports_since.clone(),
+                                ignore_non_obds_2: Some(ignore_non_obds_2),
+                                include_extern: Some(include_extern),
+                                include_histo_zyto: Some(include_histo_zyto),
+                                schema_versions: Some(schema_versions),
+                                show_excluded: Some(show_excluded),
+                                ..Parameters::for_period(period)
+                            },
+                            GroupSizes {
+                                meldende_stellen: Some(&meldende_stellen),
+                                excluded: excluded.as_deref(),
+                                ..GroupSizes::new(&items)
+                            },
+                        )
+                        .to_value(),
+                    ),
+                }
+            }
 
-            print_extern_notice(include_extern);
-            print_items(&items);
+            match output_format {
+                OutputFormat::Text => print_extern_notice(include_extern),
+                OutputFormat::Json => print_json_blocks(blocks),
+            }
         }
         SubCommand::Export {
             pat_id,
             database,
-            host,
-            password,
-            port,
-            user,
+            snapshot,
             output,
-            year,
-            ignore_exports_since,
-            ignore_non_obds_2,
+            diagnosis,
+            filter,
             xls_csv,
-            include_extern,
-            include_histo_zyto,
         } => {
-            let password = request_password_if_none(password);
-            let year = sanitize_year(&year);
-
-            let _ = term.write_line(
-                &style(format!("Warte auf Daten für das Diagnosejahr {}...", year))
-                    .blue()
-                    .bright()
-                    .to_string(),
-            );
-
-            let db = DatabaseSource::new(&database, &host, &password, port, &user);
-            let items = db
-                .export(
-                    &year,
-                    &ignore_exports_since.unwrap_or("9999-12-31".into()),
+            let periods = diagnosis_periods(&diagnosis)?;
+            let mut db = source(
+                &config,
+                &database,
+                snapshot.as_deref(),
+                profile.as_deref(),
+                output_format,
+            )?;
+            let Filter {
+                ignore_exports_since,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            } = config.filter(&filter);
+
+            let mut items = vec![];
+            let mut counts = vec![];
+            let mut blocks = vec![];
+            for period in &periods {
+                let icd_groups =
+                    icd_group_versions.select_for_period(grouping_version.as_deref(), period)?;
+                print_wait_message(output_format, &format!("Warte auf Daten für {}...", period));
+
+                let period_items = db.export(
+                    period,
+                    ignore_exports_since.as_deref().unwrap_or("9999-12-31"),
                     ignore_non_obds_2,
                     pat_id,
                     include_extern,
                     include_histo_zyto,
-                )
-                .map_err(|_e| "Fehler bei Zugriff auf die Datenbank")?;
-
-            let _ = term.clear_last_lines(1);
+                )?;
+
+                clear_wait_message(output_format);
+
+                // Created within the loop to use the ICD-10 groups version of the period
+                if output_format == OutputFormat::Json {
+                    blocks.push(
+                        JsonOutput::new(
+                            "export",
+                            &icd_groups,
+                            Parameters {
+                                snapshot: snapshot
+                                    .as_ref()
+                                    .and_then(|snapshot| snapshot.to_str())
+                                    .map(|snapshot| snapshot.to_string()),
+                                output: output.to_str().map(|output| output.to_string()),
+                                ignore_exports_since: ignore_exports_since.clone(),
+                                ignore_non_obds_2: Some(ignore_non_obds_2),
+                                include_extern: Some(include_extern),
+                                include_histo_zyto: Some(include_histo_zyto),
+                                ..Parameters::for_period(period)
+                            },
+                            ExportResult {
+                                count: period_items.len(),
+                            },
+                        )
+                        .to_value(),
+                    );
+                }
 
-            let writer_builder = &mut WriterBuilder::new();
-            let mut writer_builder = writer_builder.has_headers(true);
-            if xls_csv {
-                writer_builder = writer_builder.delimiter(b';');
+                counts.push(period_items.len());
+                items.extend(period_items);
             }
-            let mut writer = writer_builder
-                .from_path(output.as_path())
-                .expect("writeable file");
-
-            items
-                .iter()
-                .for_each(|item| writer.serialize(item).unwrap());
 
-            let _ = term.write_line(
-                &style(format!(
-                    "{} Conditions für das Jahr {} in Datei '{}' exportiert",
-                    items.len(),
-                    year,
-                    output.to_str().unwrap_or_default()
-                ))
-                .green()
-                .to_string(),
-            );
+            write_csv(output.as_path(), &items, xls_csv)
+                .map_err(|err| format!("Kann Ausgabedatei nicht schreiben: {}", err))?;
+
+            match output_format {
+                OutputFormat::Text => {
+                    for (period, count) in periods.iter().zip(counts) {
+                        let _ = term.write_line(
+                            &style(format!(
+                                "{} Conditions für {} in Datei '{}' exportiert",
+                                count,
+                                period,
+                                output.to_str().unwrap_or_default()
+                            ))
+                            .green()
+                            .to_string(),
+                        );
+                    }
 
-            print_extern_notice(include_extern);
+                    print_extern_notice(include_extern);
+                }
+                OutputFormat::Json => print_json_blocks(blocks),
+            }
         }
         SubCommand::Compare {
             pat_id,
             database,
-            host,
-            password,
-            port,
-            user,
+            snapshot,
             file,
-            year,
-            ignore_exports_since,
-            ignore_non_obds_2,
-            include_extern,
-            include_histo_zyto,
+            diagnosis,
+            filter,
+            max_missing,
+            max_changed,
+            output_dir,
+            xls_csv,
+            baseline: baseline_file,
         } => {
-            let password = request_password_if_none(password);
-            let year = sanitize_year(&year);
-
-            let _ = term.write_line(
-                &style(format!("Warte auf Daten für das Diagnosejahr {}...", year))
-                    .blue()
-                    .bright()
-                    .to_string(),
-            );
-
-            let db = DatabaseSource::new(&database, &host, &password, port, &user);
-            let db_items = db
-                .export(
-                    &year,
-                    &ignore_exports_since.unwrap_or("9999-12-31".into()),
+            let periods = diagnosis_periods(&diagnosis)?;
+            let baseline = baseline_file.as_deref().map(Baseline::load).transpose()?;
+            let today = Local::now().date_naive();
+            let mut db = source(
+                &config,
+                &database,
+                snapshot.as_deref(),
+                profile.as_deref(),
+                output_format,
+            )?;
+            let Filter {
+                ignore_exports_since,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            } = config.filter(&filter);
+
+            let all_csv_items = opal::OpalCsvFile::export(file.as_path())?;
+
+            let mut blocks = vec![];
+            for period in &periods {
+                let icd_groups =
+                    icd_group_versions.select_for_period(grouping_version.as_deref(), period)?;
+                print_wait_message(output_format, &format!("Warte auf Daten für {}...", period));
+
+                let db_items = db.export(
+                    period,
+                    ignore_exports_since.as_deref().unwrap_or("9999-12-31"),
                     ignore_non_obds_2,
                     pat_id,
                     include_extern,
                     include_histo_zyto,
-                )
-                .map_err(|_e| "Fehler bei Zugriff auf die Datenbank")?;
+                )?;
 
-            let _ = term.clear_last_lines(1);
+                clear_wait_message(output_format);
 
-            let csv_items =
-                opal::OpalCsvFile::export(file.as_path()).map_err(|_e| "Kann Datei nicht lesen")?;
+                let csv_items = period_csv_items(&all_csv_items, period, periods.len());
 
-            let mut not_in_csv = db_items
-                .iter()
-                .filter(|&db_item| {
-                    !csv_items
-                        .iter()
-                        .map(|csv_item| &csv_item.condition_id)
-                        .contains(&db_item.condition_id)
-                })
-                .collect::<Vec<_>>();
+                let mut condition_diffs = Check::compare(&db_items, &csv_items, &icd_groups);
 
-            print_extern_notice(include_extern);
+                if let Some(ref baseline) = baseline {
+                    condition_diffs
+                        .suppress(&baseline.condition_ids(today, &db.meldung_conditions(period)?));
+                }
+                let not_in_csv = &condition_diffs.not_in_csv;
+                let not_in_db = &condition_diffs.not_in_db;
+                let changed = &condition_diffs.changed;
 
-            let _ = term.write_line(
-                &style(format!(
-                    "{} Conditions aus der Datenbank für das Jahr {} - aber nicht in Datei '{}'",
-                    not_in_csv.len(),
-                    year,
-                    file.to_str().unwrap_or_default()
-                ))
-                .green()
-                .to_string(),
-            );
+                if not_in_csv.len() + not_in_db.len() > max_missing || changed.len() > max_changed {
+                    outcome = Outcome::Discrepancies;
+                }
 
-            let _ = term.write_line(&format!(
-                "{:<64}   {:<10}   {:<5}   {:<5}   {}",
-                "Condition-ID", "Datum", "ICD10", "", "PAT-ID"
-            ));
+                // Multiple periods use one subdirectory each
+                let period_output_dir = output_dir.as_ref().map(|output_dir| match periods.len() {
+                    1 => output_dir.to_path_buf(),
+                    _ => output_dir.join(period.label()),
+                });
 
-            not_in_csv.sort_by_key(|item| item.condition_id.to_string());
+                if let Some(ref output_dir) = period_output_dir {
+                    write_condition_diffs(output_dir, &condition_diffs, xls_csv, &icd_groups)?;
+                }
 
-            not_in_csv
-                .iter()
-                .for_each(|&item| match Check::is_relevant(&item.icd_10_code) {
-                    true => {
-                        let _ = term.write_line(&format!(
-                            "{:<64}   {:<10}   {:<5}   {:<5}   {}",
-                            item.condition_id,
-                            item.diagnosis_date,
-                            style(&item.icd_10_code).bold().red(),
-                            "",
-                            match &item.pat_id {
-                                Some(ref pat_id) => pat_id.to_string(),
-                                _ => "".to_string(),
-                            }
-                        ));
-                    }
-                    false => {
-                        let _ = term.write_line(&format!(
-                            "{:<64}   {:<10}   {:<5}   {:<5}   {}",
-                            item.condition_id,
-                            item.diagnosis_date,
-                            item.icd_10_code,
-                            "",
-                            match &item.pat_id {
-                                Some(ref pat_id) => pat_id.to_string(),
-                                _ => "".to_string(),
-                            }
-                        ));
-                    }
-                });
+                if output_format == OutputFormat::Json {
+                    blocks.push(
+                        JsonOutput::new(
+                            "compare",
+                            &icd_groups,
+                            Parameters {
+                                snapshot: snapshot
+                                    .as_ref()
+                                    .and_then(|snapshot| snapshot.to_str())
+                                    .map(|snapshot| snapshot.to_string()),
+                                file: file.to_str().map(|file| file.to_string()),
+                                baseline: baseline_file
+                                    .as_ref()
+                                    .and_then(|baseline_file| baseline_file.to_str())
+                                    .map(|baseline_file| baseline_file.to_string()),
+                                output: period_output_dir
+                                    .as_ref()
+                                    .and_then(|output_dir| output_dir.to_str())
+                                    .map(|output_dir| output_dir.to_string()),
+                                ignore_exports_since: ignore_exports_since.clone(),
+                                ignore_non_obds_2: Some(ignore_non_obds_2),
+                                include_extern: Some(include_extern),
+                                include_histo_zyto: Some(include_histo_zyto),
+                                ..Parameters::for_period(period)
+                            },
+                            CompareResult::new(&condition_diffs, &icd_groups),
+                        )
+                        .to_value(),
+                    );
+                    continue;
+                }
 
-            let mut not_in_db = csv_items
-                .iter()
-                .filter(|&csv_item| {
-                    !db_items
-                        .iter()
-                        .map(|db_item| &db_item.condition_id)
-                        .contains(&csv_item.condition_id)
-                })
-                .collect::<Vec<_>>();
+                print_period_header(period, periods.len());
 
-            let _ = term.write_line(
-                &style(format!(
-                    "{} Conditions aus Datei '{}' - aber nicht in der Datenbank für das Jahr {}",
-                    not_in_db.len(),
-                    file.to_str().unwrap_or_default(),
-                    year
-                ))
-                .green()
-                .to_string(),
-            );
+                print_condition_diffs(
+                    &condition_diffs,
+                    &format!("der Datenbank für {}", period),
+                    "DB",
+                    &file,
+                    &icd_groups,
+                );
 
-            let _ = term.write_line(&format!(
-                "{:<64}   {:<10}   {:<5}",
-                "Condition-ID", "Datum", "ICD10"
-            ));
+                if let Some(output_dir) = period_output_dir {
+                    let _ = term.write_line(
+                        &style(format!(
+                            "Abweichungen in Verzeichnis '{}' gespeichert",
+                            output_dir.to_str().unwrap_or_default()
+                        ))
+                        .green()
+                        .to_string(),
+                    );
+                }
+            }
 
-            not_in_db.sort_by_key(|item| item.condition_id.to_string());
+            match output_format {
+                OutputFormat::Text => print_extern_notice(include_extern),
+                OutputFormat::Json => print_json_blocks(blocks),
+            }
+        }
+        SubCommand::Baseline {
+            pat_id,
+            database,
+            snapshot,
+            file,
+            diagnosis,
+            filter,
+            output,
+            reason,
+            expires,
+        } => {
+            let periods = diagnosis_periods(&diagnosis)?;
+            let mut baseline = Baseline::load_or_default(&output)?;
+            let mut db = source(
+                &config,
+                &database,
+                snapshot.as_deref(),
+                profile.as_deref(),
+                output_format,
+            )?;
+            let Filter {
+                ignore_exports_since,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            } = config.filter(&filter);
+
+            let all_csv_items = opal::OpalCsvFile::export(file.as_path())?;
+
+            let today = Local::now().date_naive();
+            let mut changes = vec![];
+            for period in &periods {
+                let icd_groups =
+                    icd_group_versions.select_for_period(grouping_version.as_deref(), period)?;
+                print_wait_message(output_format, &format!("Warte auf Daten für {}...", period));
+
+                let db_items = db.export(
+                    period,
+                    ignore_exports_since.as_deref().unwrap_or("9999-12-31"),
+                    ignore_non_obds_2,
+                    pat_id,
+                    include_extern,
+                    include_histo_zyto,
+                )?;
 
-            not_in_db
-                .iter()
-                .for_each(|item| match Check::is_relevant(&item.icd_10_code) {
-                    true => {
-                        let _ = term.write_line(&format!(
-                            "{:<64}   {:<10}   {:<5}",
-                            item.condition_id,
-                            item.diagnosis_date,
-                            style(&item.icd_10_code).bold().red()
-                        ));
-                    }
-                    false => {
-                        let _ = term.write_line(&format!(
-                            "{:<64}   {:<10}   {:<5}",
-                            item.condition_id, item.diagnosis_date, item.icd_10_code
-                        ));
+                clear_wait_message(output_format);
+
+                let csv_items = period_csv_items(&all_csv_items, period, periods.len());
+                let condition_diffs = Check::compare(&db_items, &csv_items, &icd_groups);
+
+                let mut result = BaselineResult::default();
+                for condition_id in condition_diffs.condition_ids() {
+                    match baseline.add(condition_id, &reason, expires.as_deref(), today) {
+                        BaselineChange::Added => result.added += 1,
+                        BaselineChange::Renewed => result.renewed += 1,
+                        BaselineChange::Existing => result.existing += 1,
                     }
-                });
+                }
+                changes.push((period, icd_groups, result));
+            }
 
-            let mut icd10diff = db_items
-                .iter()
-                .filter(|db_item| {
-                    csv_items
-                        .iter()
-                        .map(|db_item| &db_item.condition_id)
-                        .contains(&db_item.condition_id)
-                })
-                .filter(|db_item| {
-                    !csv_items
-                        .iter()
-                        .map(|csv_item| {
-                            format!("{}-{}", csv_item.condition_id, csv_item.icd_10_code)
+            baseline.save(&output)?;
+
+            match output_format {
+                OutputFormat::Json => print_json_blocks(
+                    changes
+                        .into_iter()
+                        .map(|(period, icd_groups, result)| {
+                            JsonOutput::new(
+                                "baseline",
+                                &icd_groups,
+                                Parameters {
+                                    snapshot: snapshot
+                                        .as_ref()
+                                        .and_then(|snapshot| snapshot.to_str())
+                                        .map(|snapshot| snapshot.to_string()),
+                                    file: file.to_str().map(|file| file.to_string()),
+                                    output: output.to_str().map(|output| output.to_string()),
+                                    ignore_exports_since: ignore_exports_since.clone(),
+                                    ignore_non_obds_2: Some(ignore_non_obds_2),
+                                    include_extern: Some(include_extern),
+                                    include_histo_zyto: Some(include_histo_zyto),
+                                    ..Parameters::for_period(period)
+                                },
+                                BaselineResult {
+                                    entries: baseline.entries.len(),
+                                    ..result
+                                },
+                            )
+                            .to_value()
                         })
-                        .contains(&format!("{}-{}", db_item.condition_id, db_item.icd_10_code))
-                })
-                .map(|db_item| DiffRecord {
-                    pat_id: db_item.pat_id.as_ref().map(|pat_id| pat_id.to_string()),
-                    condition_id: db_item.condition_id.to_string(),
-                    diagnosis_date: db_item.diagnosis_date.to_string(),
-                    csv_icd10_code: db_item.icd_10_code.to_string(),
-                    db_icd10_code: csv_items
-                        .iter()
-                        .filter(|csv_item| csv_item.condition_id == db_item.condition_id)
-                        .collect_vec()
-                        .first()
-                        .unwrap()
-                        .icd_10_code
+                        .collect(),
+                ),
+                OutputFormat::Text => {
+                    let (added, renewed, existing) = changes.iter().fold(
+                        (0, 0, 0),
+                        |(added, renewed, existing), (_, _, result)| {
+                            (
+                                added + result.added,
+                                renewed + result.renewed,
+                                existing + result.existing,
+                            )
+                        },
+                    );
+                    let _ = term.write_line(
+                        &style(format!(
+                            "{} neue Abweichungen in Baseline-Datei '{}' aufgenommen, {} abgelaufene erneuert, {} bereits enthalten",
+                            added,
+                            output.to_str().unwrap_or_default(),
+                            renewed,
+                            existing
+                        ))
+                        .green()
                         .to_string(),
-                })
-                .collect::<Vec<_>>();
+                    );
+                }
+            }
+        }
+        SubCommand::CompareProtocol {
+            pat_id,
+            protocol_file,
+            file,
+            diagnosis,
+            filter,
+            max_missing,
+            max_changed,
+            output_dir,
+            xls_csv,
+        } => {
+            let periods = diagnosis_periods(&diagnosis)?;
+            let rules = config.entry_rules()?;
+            let Filter {
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+                ..
+            } = config.filter(&filter);
+
+            let protocol = LkrExportProtocolFile::parse_path(protocol_file.as_path())?;
+            let all_csv_items = opal::OpalCsvFile::export(file.as_path())?;
+
+            let mut blocks = vec![];
+            for period in &periods {
+                let icd_groups =
+                    icd_group_versions.select_for_period(grouping_version.as_deref(), period)?;
+
+                let protocol_items = protocol.conditions(
+                    period,
+                    &rules,
+                    ignore_non_obds_2,
+                    pat_id,
+                    include_extern,
+                    include_histo_zyto,
+                );
+                let csv_items = period_csv_items(&all_csv_items, period, periods.len());
 
-            let _ = term.write_line(
-                &style(format!(
-                    "{} Conditions mit Unterschied im ICD10-Code",
-                    icd10diff.len()
-                ))
-                .green()
-                .to_string(),
+                let condition_diffs = Check::compare(&protocol_items, &csv_items, &icd_groups);
+
+                if condition_diffs.not_in_csv.len() + condition_diffs.not_in_db.len() > max_missing
+                    || condition_diffs.changed.len() > max_changed
+                {
+                    outcome = Outcome::Discrepancies;
+                }
+
+                // Multiple periods use one subdirectory each
+                let period_output_dir = output_dir.as_ref().map(|output_dir| match periods.len() {
+                    1 => output_dir.to_path_buf(),
+                    _ => output_dir.join(period.label()),
+                });
+
+                if let Some(ref output_dir) = period_output_dir {
+                    write_condition_diffs(output_dir, &condition_diffs, xls_csv, &icd_groups)?;
+                }
+
+                if output_format == OutputFormat::Json {
+                    blocks.push(
+                        JsonOutput::new(
+                            "compare-protocol",
+                            &icd_groups,
+                            Parameters {
+                                file: file.to_str().map(|file| file.to_string()),
+                                protocol_file: protocol_file
+                                    .to_str()
+                                    .map(|protocol_file| protocol_file.to_string()),
+                                output: period_output_dir
+                                    .as_ref()
+                                    .and_then(|output_dir| output_dir.to_str())
+                                    .map(|output_dir| output_dir.to_string()),
+                                ignore_non_obds_2: Some(ignore_non_obds_2),
+                                include_extern: Some(include_extern),
+                                include_histo_zyto: Some(include_histo_zyto),
+                                ..Parameters::for_period(period)
+                            },
+                            CompareResult::new(&condition_diffs, &icd_groups),
+                        )
+                        .to_value(),
+                    );
+                    continue;
+                }
+
+                print_period_header(period, periods.len());
+
+                print_condition_diffs(
+                    &condition_diffs,
+                    &format!(
+                        "dem LKR-Export-Protokoll '{}' für {}",
+                        protocol_file.to_str().unwrap_or_default(),
+                        period
+                    ),
+                    "LKR",
+                    &file,
+                    &icd_groups,
+                );
+
+                if let Some(output_dir) = period_output_dir {
+                    let _ = term.write_line(
+                        &style(format!(
+                            "Abweichungen in Verzeichnis '{}' gespeichert",
+                            output_dir.to_str().unwrap_or_default()
+                        ))
+                        .green()
+                        .to_string(),
+                    );
+                }
+            }
+
+            if output_format == OutputFormat::Json {
+                print_json_blocks(blocks);
+            }
+        }
+        SubCommand::CompareCounts {
+            database,
+            snapshot,
+            file,
+            year,
+            filter,
+            tolerance,
+        } => {
+            let mut db = source(
+                &config,
+                &database,
+                snapshot.as_deref(),
+                profile.as_deref(),
+                output_format,
+            )?;
+            let Filter {
+                ignore_exports_since,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            } = config.filter(&filter);
+            let year = sanitize_year(&year);
+            let period = DiagnosisPeriod::Year(year.to_string());
+
+            let csv_items = opal::OpalCsvFile::check(file.as_path(), &icd_groups)?;
+
+            print_wait_message(
+                output_format,
+                &format!("Warte auf Daten für das Diagnosejahr {}...", year),
             );
 
-            icd10diff.sort_by_key(|item| item.condition_id.to_string());
+            let db_items = db.check(
+                &period,
+                ignore_exports_since.as_deref().unwrap_or("9999-12-31"),
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+                false,
+                &icd_groups,
+            )?;
 
-            let _ = term.write_line(&format!(
-                "{:<64}   {:<10}   {:<5}   {:<5}   {}",
-                "Condition-ID", "Datum", "CSV", "DB", "PAT-ID"
-            ));
+            clear_wait_message(output_format);
 
-            icd10diff.iter().for_each(|item| {
-                let _ = term.write_line(&format!(
-                    "{:<64}   {:<10}   {:<5}   {:<5}   {}",
-                    item.condition_id,
-                    item.diagnosis_date,
-                    match Check::is_relevant(&item.csv_icd10_code) {
-                        true => style(format!("{:<5}", item.csv_icd10_code)).bold().red(),
-                        _ => style(format!("{:<5}", item.csv_icd10_code)),
-                    },
-                    match Check::is_relevant(&item.db_icd10_code) {
-                        true => style(format!("{:<5}", item.db_icd10_code)).bold().red(),
-                        _ => style(format!("{:<5}", item.db_icd10_code)),
+            let items = Check::compare_sizes(&csv_items, &db_items);
+
+            outcome = Outcome::from_discrepancies(items.iter().any(|item| item.exceeds(tolerance)));
+
+            match output_format {
+                OutputFormat::Text => {
+                    print_extern_notice(include_extern);
+                    print_size_diffs(&items, tolerance, &icd_groups);
+                }
+                OutputFormat::Json => print_json(&JsonOutput::new(
+                    "compare-counts",
+                    &icd_groups,
+                    Parameters {
+                        snapshot: snapshot
+                            .as_ref()
+                            .and_then(|snapshot| snapshot.to_str())
+                            .map(|snapshot| snapshot.to_string()),
+                        file: file.to_str().map(|file| file.to_string()),
+                        year: Some(year),
+                        ignore_exports_since,
+                        ignore_non_obds_2: Some(ignore_non_obds_2),
+                        include_extern: Some(include_extern),
+                        include_histo_zyto: Some(include_histo_zyto),
+                        tolerance: Some(tolerance),
+                        ..Default::default()
                     },
-                    match &item.pat_id {
-                        Some(ref pat_id) => pat_id.to_string(),
-                        _ => "".to_string(),
-                    }
-                ));
-            });
+                    CompareCountsResult::new(&items, tolerance),
+                )),
+            }
         }
-        SubCommand::CheckExport {
+        SubCommand::Report {
+            pat_id,
             database,
-            host,
-            password,
-            port,
-            user,
+            snapshot,
             file,
+            output,
+            year,
+            filter,
+            tolerance,
+            protocol_file,
             package,
+            baseline: baseline_file,
         } => {
-            let password = request_password_if_none(password);
+            let baseline = baseline_file.as_deref().map(Baseline::load).transpose()?;
+            let mut db = source(
+                &config,
+                &database,
+                snapshot.as_deref(),
+                profile.as_deref(),
+                output_format,
+            )?;
+            let Filter {
+                ignore_exports_since,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            } = config.filter(&filter);
+            let year = sanitize_year(&year);
+            let period = DiagnosisPeriod::Year(year.to_string());
 
-            let _ = term.write_line(
-                &style(format!(
-                    "Warte auf Daten für den LKR-Export '{}'...",
-                    package
-                ))
-                .blue()
-                .bright()
-                .to_string(),
-            );
+            let csv_sizes = opal::OpalCsvFile::check(file.as_path(), &icd_groups)?;
+            let csv_items = opal::OpalCsvFile::export(file.as_path())?;
+            let protocol_file_content = match protocol_file {
+                Some(ref protocol_file) => {
+                    Some(LkrExportProtocolFile::parse_file(protocol_file.as_path())?)
+                }
+                None => None,
+            };
 
-            let db = DatabaseSource::new(&database, &host, &password, port, &user);
+            print_wait_message(
+                output_format,
+                &format!("Warte auf Daten für das Diagnosejahr {}...", year),
+            );
 
-            let db_entries = db
-                .exported(package)
-                .map_err(|_e| "Fehler bei Zugriff auf die Datenbank")?;
+            let ignore_exports_since_value =
+                ignore_exports_since.as_deref().unwrap_or("9999-12-31");
+            let db_sizes = db.check(
+                &period,
+                ignore_exports_since_value,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+                false,
+                &icd_groups,
+            )?;
+            let db_items = db.export(
+                &period,
+                ignore_exports_since_value,
+                ignore_non_obds_2,
+                pat_id,
+                include_extern,
+                include_histo_zyto,
+            )?;
+            let export_check = match protocol_file_content {
+                Some(ref protocol_file_content) => {
+                    let db_entries = db.exported(package)?;
+                    Some(ExportCheck::new(&db_entries, protocol_file_content))
+                }
+                None => None,
+            };
 
-            let db_meldungen = db_entries
-                .iter()
-                .map(|entry| LkrExportProtocolFile::parse(&entry.1))
-                .filter(|entry| entry.is_ok())
-                .flat_map(|entry| entry.unwrap().meldungen())
-                .filter(|meldung| meldung.id().is_some())
-                .map(|meldung| (meldung.id().unwrap(), meldung))
-                .collect::<HashMap<_, _>>();
-
-            let xml_meldungen = LkrExportProtocolFile::parse_file(file.as_path())
-                .map_err(|_e| "Fehler bei Zugriff auf die Protokolldatei")?
-                .meldungen()
-                .into_iter()
-                .filter(|meldung| meldung.id().is_some())
-                .map(|meldung| (meldung.id().unwrap(), meldung))
-                .collect::<HashMap<_, _>>();
-
-            let missing_xml_ids = db_meldungen
-                .keys()
-                .filter(|&key| !xml_meldungen.contains_key(key))
-                .collect_vec();
-
-            let _ = term.clear_last_lines(1);
+            clear_wait_message(output_format);
 
-            let _ = term.write_line(
-                &style(format!(
-                    "{} Datenbankeinträge mit {} Meldungen abgerufen",
-                    db_entries.len(),
-                    db_meldungen.len()
-                ))
-                .green()
-                .to_string(),
-            );
+            let group_sizes = Check::compare_sizes(&csv_sizes, &db_sizes);
+            let mut condition_diffs = Check::compare(&db_items, &csv_items, &icd_groups);
 
-            fn print_missing_ids(missing_ids: &[&String], term: &Term) {
-                missing_ids.iter().sorted().for_each(|&item| {
-                    let _ = term.write_line(&format!(
-                        "{} ({})",
-                        item,
-                        to_database_id(item).unwrap_or("?".into())
-                    ));
-                });
+            if let Some(ref baseline) = baseline {
+                condition_diffs.suppress(
+                    &baseline
+                        .condition_ids(Local::now().date_naive(), &db.meldung_conditions(&period)?),
+                );
             }
 
-            if db_meldungen.len() != xml_meldungen.len() {
-                let _ = term.write_line(
-                    &style("\nNicht übereinstimmende Anzahl an Meldungen:")
-                        .yellow()
+            outcome = Outcome::from_discrepancies(
+                group_sizes.iter().any(|item| item.exceeds(tolerance))
+                    || condition_diffs.has_discrepancies()
+                    || export_check
+                        .as_ref()
+                        .is_some_and(|export_check| export_check.has_discrepancies()),
+            );
+
+            let parameters = Parameters {
+                snapshot: snapshot
+                    .as_ref()
+                    .and_then(|snapshot| snapshot.to_str())
+                    .map(|snapshot| snapshot.to_string()),
+                file: file.to_str().map(|file| file.to_string()),
+                output: output.to_str().map(|output| output.to_string()),
+                year: Some(year),
+                ignore_exports_since,
+                ignore_non_obds_2: Some(ignore_non_obds_2),
+                include_extern: Some(include_extern),
+                include_histo_zyto: Some(include_histo_zyto),
+                tolerance: Some(tolerance),
+                protocol_file: protocol_file
+                    .as_ref()
+                    .and_then(|protocol_file| protocol_file.to_str())
+                    .map(|protocol_file| protocol_file.to_string()),
+                package: protocol_file.as_ref().map(|_| package),
+                baseline: baseline_file
+                    .as_ref()
+                    .and_then(|baseline_file| baseline_file.to_str())
+                    .map(|baseline_file| baseline_file.to_string()),
+                ..Default::default()
+            };
+
+            let report = Report {
+                parameters: &parameters,
+                created_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                tolerance,
+                group_sizes: &group_sizes,
+                condition_diffs: &condition_diffs,
+                export_check: export_check.as_ref(),
+                icd_groups: &icd_groups,
+            };
+
+            fs::write(output.as_path(), report.to_html())
+                .map_err(|err| format!("Kann Ausgabedatei nicht schreiben: {}", err))?;
+
+            match output_format {
+                OutputFormat::Text => {
+                    print_extern_notice(include_extern);
+                    let _ = term.write_line(
+                        &style(format!(
+                            "Bericht für das Jahr {} in Datei '{}' gespeichert",
+                            parameters.year.as_deref().unwrap_or_default(),
+                            output.to_str().unwrap_or_default()
+                        ))
+                        .green()
                         .to_string(),
+                    );
+                }
+                OutputFormat::Json => print_json(&JsonOutput::new(
+                    "report",
+                    &icd_groups,
+                    parameters,
+                    ReportResult {
+                        groups: CompareCountsResult::new(&group_sizes, tolerance),
+                        conditions: CompareResult::new(&condition_diffs, &icd_groups),
+                        check_export: export_check.as_ref().map(CheckExportResult::from),
+                    },
+                )),
+            }
+        }
+        SubCommand::Trend {
+            database,
+            snapshot,
+            from_year,
+            to_year,
+            filter,
+            highlight,
+        } => {
+            let years = diagnosis_years(&from_year, &to_year)?;
+            let mut db = source(
+                &config,
+                &database,
+                snapshot.as_deref(),
+                profile.as_deref(),
+                output_format,
+            )?;
+            let Filter {
+                ignore_exports_since,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            } = config.filter(&filter);
+
+            let mut sizes_by_year = vec![];
+            for year in &years {
+                print_wait_message(
+                    output_format,
+                    &format!("Warte auf Daten für das Diagnosejahr {}...", year),
                 );
-                let _ = term.write_line(&format!(
-                    "Datenbank:      {:>10}\nProtokolldatei: {:>10}",
-                    db_meldungen.len(),
-                    xml_meldungen.len()
-                ));
 
-                let missing_db_ids = xml_meldungen
-                    .keys()
-                    .filter(|&key| !db_meldungen.contains_key(key))
-                    .collect_vec();
+                sizes_by_year.push(db.check(
+                    &DiagnosisPeriod::Year(year.to_string()),
+                    ignore_exports_since.as_deref().unwrap_or("9999-12-31"),
+                    ignore_non_obds_2,
+                    include_extern,
+                    include_histo_zyto,
+                    false,
+                    &icd_groups,
+                )?);
 
-                if !missing_db_ids.is_empty() {
-                    let _ = term.write_line(
-                        &style("\nIn der Datenbank fehlende Meldungen:")
-                            .yellow()
-                            .to_string(),
-                    );
+                clear_wait_message(output_format);
+            }
 
-                    print_missing_ids(&missing_db_ids, &term);
+            let items = Check::trend(&sizes_by_year);
+            let sum = Icd10GroupTrend::sum(
+                "Summe (C**.*/D**.*)",
+                items.iter().filter(|item| item.name != OTHER),
+                years.len(),
+            );
+            let total = Icd10GroupTrend::sum("Gesamtsumme", items.iter(), years.len());
+
+            match output_format {
+                OutputFormat::Text => {
+                    print_trend(&years, &items, &sum, &total, highlight, &icd_groups);
+                    print_extern_notice(include_extern);
                 }
+                OutputFormat::Json => print_json(&JsonOutput::new(
+                    "trend",
+                    &icd_groups,
+                    Parameters {
+                        snapshot: snapshot
+                            .as_ref()
+                            .and_then(|snapshot| snapshot.to_str())
+                            .map(|snapshot| snapshot.to_string()),
+                        from_year: years.first().cloned(),
+                        to_year: years.last().cloned(),
+                        ignore_exports_since,
+                        ignore_non_obds_2: Some(ignore_non_obds_2),
+                        include_extern: Some(include_extern),
+                        include_histo_zyto: Some(include_histo_zyto),
+                        highlight: Some(highlight),
+                        ..Default::default()
+                    },
+                    TrendResult {
+                        years: &years,
+                        groups: items.iter().map(GroupTrend::from).collect(),
+                        sum: GroupTrend::from(&sum),
+                        total: GroupTrend::from(&total),
+                    },
+                )),
+            }
+        }
+        SubCommand::Explain {
+            database,
+            snapshot,
+            cond_id,
+            year,
+            patient_id,
+            filter,
+        } => {
+            let mut db = source(
+                &config,
+                &database,
+                snapshot.as_deref(),
+                profile.as_deref(),
+                output_format,
+            )?;
+            let Filter {
+                ignore_exports_since,
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            } = config.filter(&filter);
+            let period = year.map(|year| DiagnosisPeriod::Year(sanitize_year(&year)));
+
+            print_wait_message(
+                output_format,
+                &match period {
+                    Some(ref period) => format!("Warte auf Daten für {}...", period),
+                    None => format!(
+                        "Warte auf Daten aller Diagnosejahre für Patient '{}'...",
+                        patient_id.as_deref().unwrap_or_default()
+                    ),
+                },
+            );
 
-                if !missing_xml_ids.is_empty() {
-                    let _ = term.write_line(
-                        &style("\nIn der Protokolldatei fehlende Meldungen:")
-                            .yellow()
-                            .to_string(),
-                    );
+            let meldungen = db.explain(
+                &cond_id,
+                period.as_ref(),
+                patient_id.as_deref(),
+                ignore_exports_since.as_deref().unwrap_or("9999-12-31"),
+                ignore_non_obds_2,
+                include_extern,
+                include_histo_zyto,
+            )?;
+
+            clear_wait_message(output_format);
 
-                    print_missing_ids(&missing_xml_ids, &term);
+            let selected = meldungen.iter().any(|meldung| meldung.is_selected());
+
+            match output_format {
+                OutputFormat::Text => {
+                    print_explanation(&cond_id, &meldungen, selected);
+                    print_extern_notice(include_extern);
                 }
+                OutputFormat::Json => print_json(&JsonOutput::new(
+                    "explain",
+                    &icd_groups,
+                    Parameters {
+                        snapshot: snapshot
+                            .as_ref()
+                            .and_then(|snapshot| snapshot.to_str())
+                            .map(|snapshot| snapshot.to_string()),
+                        cond_id: Some(cond_id.to_string()),
+                        patient_id: patient_id.clone(),
+                        ignore_exports_since,
+                        ignore_non_obds_2: Some(ignore_non_obds_2),
+                        include_extern: Some(include_extern),
+                        include_histo_zyto: Some(include_histo_zyto),
+                        ..period
+                            .as_ref()
+                            .map(Parameters::for_period)
+                            .unwrap_or_default()
+                    },
+                    ExplainResult {
+                        condition_id: &cond_id,
+                        selected,
+                        meldungen: &meldungen,
+                    },
+                )),
             }
+        }
+        SubCommand::CheckExport {
+            database,
+            snapshot,
+            file,
+            package,
+            show_diff,
+            diff_dir,
+        } => {
+            let mut db = source(
+                &config,
+                &database,
+                snapshot.as_deref(),
+                profile.as_deref(),
+                output_format,
+            )?;
+
+            print_wait_message(
+                output_format,
+                &format!("Warte auf Daten für den LKR-Export '{}'...", package),
+            );
 
-            let multiple_meldung_entries = db_entries
-                .iter()
-                .map(|(lkr_meldung, meldung)| (lkr_meldung, LkrExportProtocolFile::parse(meldung)))
-                .filter_map(|(lkr_meldung, meldung)| {
-                    if meldung.unwrap().meldungen().len() > 1 {
-                        Some(lkr_meldung)
-                    } else {
-                        None
-                    }
-                })
-                .sorted()
-                .collect_vec();
-
-            if !multiple_meldung_entries.is_empty() {
-                let _ = term.write_line(
-                    &style("\nFolgende Einträge in `lkr_meldung_export` haben mehrere Meldungsinhalte in `xml_daten`:")
-                        .yellow()
-                        .to_string(),
-                );
+            let db_entries = db.exported(package)?;
 
-                multiple_meldung_entries.iter().for_each(|&item| {
-                    let _ = term.write_line(&item.to_string());
-                });
+            let protocol_file = LkrExportProtocolFile::parse_file(file.as_path())?;
+
+            let export_check = ExportCheck::new(&db_entries, &protocol_file);
+
+            clear_wait_message(output_format);
+
+            outcome = Outcome::from_discrepancies(export_check.has_discrepancies());
+
+            if let Some(ref diff_dir) = diff_dir {
+                write_content_diffs(diff_dir, &export_check.content_diffs)?;
             }
 
-            let different_content = db_meldungen
-                .iter()
-                .filter(|(id, _)| !missing_xml_ids.contains(id))
-                .filter(|(id, meldung)| {
-                    xml_meldungen
-                        .get(&id.to_string())
-                        .unwrap_or(&Meldung {
-                            raw_value: String::new(),
-                        })
-                        .sanitized_xml_string()
-                        != meldung.sanitized_xml_string()
-                })
-                .map(|(_, meldung)| meldung.id().unwrap_or("?".into()))
-                .collect_vec();
-
-            if !different_content.is_empty() {
-                let _ = term.write_line(
-                    &style(&format!(
-                        "\nFolgende {} Meldungen unterscheiden sich in der Datenbank und der Protokolldatei:",
-                        different_content.len()
-                    ))
-                    .yellow()
-                    .to_string(),
-                );
+            if output_format == OutputFormat::Json {
+                let result = CheckExportResult::from(&export_check);
+                print_json(&JsonOutput::new(
+                    "check-export",
+                    &icd_groups,
+                    Parameters {
+                        snapshot: snapshot
+                            .as_ref()
+                            .and_then(|snapshot| snapshot.to_str())
+                            .map(|snapshot| snapshot.to_string()),
+                        file: file.to_str().map(|file| file.to_string()),
+                        package: Some(package),
+                        show_diff: Some(show_diff),
+                        diff_dir: diff_dir
+                            .as_ref()
+                            .and_then(|diff_dir| diff_dir.to_str())
+                            .map(|diff_dir| diff_dir.to_string()),
+                        ..Default::default()
+                    },
+                    match show_diff {
+                        true => result.with_changes(&export_check),
+                        false => result,
+                    },
+                ));
+                return Ok(outcome);
+            }
 
-                let _ = term.write_line(
-                    "Dies kann auch aufgrund der verwendeten XML-Encodierung auftreten und bedeutet nicht immer eine inhaltliche Abweichung."
-                );
+            print_export_check(&export_check, show_diff);
 
-                different_content
-                    .iter()
-                    .sorted_by(|&id1, &id2| {
-                        to_database_id(id1)
-                            .unwrap_or_default()
-                            .cmp(&to_database_id(id2).unwrap_or_default())
-                    })
-                    .for_each(|id| {
-                        let _ = term.write_line(&format!(
-                            "{} ({})",
-                            id,
-                            to_database_id(id).unwrap_or("?".into())
-                        ));
-                    });
+            if let Some(diff_dir) = diff_dir.filter(|_| !export_check.content_diffs.is_empty()) {
+                let _ = term.write_line(&format!(
+                    "\nBeide Versionen abweichender Meldungen in '{}' gespeichert",
+                    diff_dir.to_str().unwrap_or_default()
+                ));
+            }
+        }
+        SubCommand::Snapshot {
+            cmd: SnapshotCommand::Create { database, output },
+        } => {
+            let settings = config.connection_settings(&database, profile.as_deref())?;
+            let password = request_password_if_none(settings.password.clone());
+            let mut db = DatabaseSource::connect(&settings, &password)?;
+
+            print_wait_message(output_format, "Erstelle Snapshot...");
+
+            let mut writer = SnapshotWriter::create(
+                output.as_path(),
+                &settings.database,
+                &chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            )?;
+            for row in db.meldung_exports()? {
+                writer.write(&row?)?;
+            }
+            let count = writer.finish()?;
+
+            clear_wait_message(output_format);
+
+            match output_format {
+                OutputFormat::Text => {
+                    let _ = term.write_line(
+                        &style(format!(
+                            "{} Einträge aus `lkr_meldung_export` in Snapshot-Datei '{}' gespeichert",
+                            count,
+                            output.to_str().unwrap_or_default()
+                        ))
+                        .green()
+                        .to_string(),
+                    );
+                }
+                OutputFormat::Json => print_json(&JsonOutput::new(
+                    "snapshot create",
+                    &icd_groups,
+                    Parameters {
+                        output: output.to_str().map(|output| output.to_string()),
+                        ..Default::default()
+                    },
+                    ExportResult { count },
+                )),
             }
         }
     }
 
-    Ok(())
+    Ok(outcome)
 }
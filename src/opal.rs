@@ -18,12 +18,16 @@
  * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
  */
 
-use std::path::Path;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 
 use csv::Reader;
 use serde::Deserialize;
 
 use crate::common::{Check, ExportData, Icd10GroupSize, Record};
+use crate::icd_groups::IcdGroups;
 
 #[derive(Deserialize)]
 pub struct OpalRecord {
@@ -33,11 +37,47 @@ pub struct OpalRecord {
     cond_coding_code: String,
 }
 
+#[derive(Debug)]
+pub struct OpalError {
+    path: PathBuf,
+    source: csv::Error,
+}
+
+impl OpalError {
+    fn new(path: &Path, source: csv::Error) -> Self {
+        OpalError {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl Display for OpalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let path = self.path.to_str().unwrap_or_default();
+        match self.source.kind() {
+            csv::ErrorKind::Io(err) if err.kind() == ErrorKind::NotFound => {
+                write!(f, "CSV-Datei '{}' nicht gefunden", path)
+            }
+            csv::ErrorKind::Io(err) if err.kind() == ErrorKind::PermissionDenied => {
+                write!(f, "Keine Berechtigung zum Lesen der CSV-Datei '{}'", path)
+            }
+            _ => write!(f, "Kann CSV-Datei '{}' nicht lesen: {}", path, self.source),
+        }
+    }
+}
+
+impl Error for OpalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
 pub struct OpalCsvFile;
 
 impl OpalCsvFile {
-    pub fn check(path: &Path) -> Result<Vec<Icd10GroupSize>, ()> {
-        let mut reader = Reader::from_path(path).expect("open file");
+    pub fn check(path: &Path, icd_groups: &IcdGroups) -> Result<Vec<Icd10GroupSize>, OpalError> {
+        let mut reader = Reader::from_path(path).map_err(|err| OpalError::new(path, err))?;
 
         let items = reader
             .deserialize::<OpalRecord>()
@@ -48,11 +88,11 @@ impl OpalCsvFile {
             })
             .collect::<Vec<_>>();
 
-        Check::collect(&items)
+        Ok(Check::collect(&items, icd_groups))
     }
 
-    pub fn export(path: &Path) -> Result<Vec<ExportData>, ()> {
-        let mut reader = Reader::from_path(path).expect("open file");
+    pub fn export(path: &Path) -> Result<Vec<ExportData>, OpalError> {
+        let mut reader = Reader::from_path(path).map_err(|err| OpalError::new(path, err))?;
 
         let items = reader
             .deserialize::<ExportData>()
@@ -0,0 +1,482 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::canonical::XmlChange;
+use crate::common::{
+    Check, ConditionDiffs, DiffRecord, Difference, ExportData, Icd10GroupSize, Icd10GroupSizeDiff,
+    Icd10GroupTrend,
+};
+use crate::filter::{DiagnosisPeriod, Exclusion, MeldendeStelleSize};
+use crate::icd_groups::IcdGroups;
+use crate::lkrexport::{to_database_id, ContentDiff, ExportCheck};
+
+/// Document written to stdout if `--output-format json` is used
+#[derive(Serialize)]
+pub struct JsonOutput<'a, T: Serialize> {
+    pub command: &'a str,
+    pub version: &'a str,
+    pub icd_groups_version: String,
+    pub parameters: Parameters,
+    pub result: T,
+}
+
+impl<'a, T: Serialize> JsonOutput<'a, T> {
+    pub fn new(
+        command: &'a str,
+        icd_groups: &IcdGroups,
+        parameters: Parameters,
+        result: T,
+    ) -> Self {
+        JsonOutput {
+            command,
+            version: env!("CARGO_PKG_VERSION"),
+            icd_groups_version: icd_groups.version.to_string(),
+            parameters,
+            result,
+        }
+    }
+
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// Run parameters of a command. Parameters not used by a command are omitted.
+#[derive(Default, Serialize)]
+pub struct Parameters {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diagnosis_from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diagnosis_to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_year: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_year: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignore_exports_since: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignore_non_obds_2: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_extern: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_histo_zyto: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_versions: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_excluded: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cond_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patient_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baseline: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_diff: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<f64>,
+}
+
+impl Parameters {
+    /// Parameters describing the given diagnosis period
+    pub fn for_period(period: &DiagnosisPeriod) -> Self {
+        match period {
+            DiagnosisPeriod::Year(year) => Parameters {
+                year: Some(year.to_string()),
+                ..Default::default()
+            },
+            DiagnosisPeriod::Range(from, to) => Parameters {
+                diagnosis_from: Some(from.to_string()),
+                diagnosis_to: Some(to.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Number of conditions by ICD-10 group including both sums printed in text output
+#[derive(Serialize)]
+pub struct GroupSizes<'a> {
+    pub groups: &'a [Icd10GroupSize],
+    pub sum: usize,
+    pub total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meldende_stellen: Option<&'a [MeldendeStelleSize]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excluded: Option<&'a [Exclusion]>,
+}
+
+impl<'a> GroupSizes<'a> {
+    pub fn new(groups: &'a [Icd10GroupSize]) -> Self {
+        GroupSizes {
+            groups,
+            sum: groups
+                .iter()
+                .filter(|item| item.name != "Other")
+                .map(|item| item.size)
+                .sum(),
+            total: groups.iter().map(|item| item.size).sum(),
+            meldende_stellen: None,
+            excluded: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GroupSizeDiff<'a> {
+    #[serde(flatten)]
+    pub item: &'a Icd10GroupSizeDiff,
+    pub delta: i64,
+    pub relative_delta: Option<f64>,
+    pub exceeds_tolerance: bool,
+}
+
+#[derive(Serialize)]
+pub struct CompareCountsResult<'a> {
+    pub groups: Vec<GroupSizeDiff<'a>>,
+}
+
+impl<'a> CompareCountsResult<'a> {
+    pub fn new(items: &'a [Icd10GroupSizeDiff], tolerance: f64) -> Self {
+        CompareCountsResult {
+            groups: items
+                .iter()
+                .map(|item| GroupSizeDiff {
+                    item,
+                    delta: item.delta(),
+                    relative_delta: item.relative_delta(),
+                    exceeds_tolerance: item.exceeds(tolerance),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GroupTrend<'a> {
+    #[serde(flatten)]
+    pub item: &'a Icd10GroupTrend,
+    pub relative_changes: Vec<Option<f64>>,
+}
+
+impl<'a> From<&'a Icd10GroupTrend> for GroupTrend<'a> {
+    fn from(item: &'a Icd10GroupTrend) -> Self {
+        GroupTrend {
+            item,
+            relative_changes: item.relative_changes(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TrendResult<'a> {
+    pub years: &'a [String],
+    pub groups: Vec<GroupTrend<'a>>,
+    pub sum: GroupTrend<'a>,
+    pub total: GroupTrend<'a>,
+}
+
+#[derive(Serialize)]
+pub struct ExplainResult<'a> {
+    pub condition_id: &'a str,
+    pub selected: bool,
+    pub meldungen: &'a [Exclusion],
+}
+
+#[derive(Default, Serialize)]
+pub struct BaselineResult {
+    pub added: usize,
+    pub renewed: usize,
+    pub existing: usize,
+    pub entries: usize,
+}
+
+#[derive(Serialize)]
+pub struct ExportResult {
+    pub count: usize,
+}
+
+#[derive(Serialize)]
+pub struct CompareItem<'a> {
+    #[serde(flatten)]
+    pub item: &'a ExportData,
+    pub is_relevant: bool,
+}
+
+impl<'a> CompareItem<'a> {
+    pub fn new(item: &'a ExportData, icd_groups: &IcdGroups) -> Self {
+        CompareItem {
+            item,
+            is_relevant: Check::is_relevant(&item.icd_10_code, icd_groups),
+        }
+    }
+}
+
+/// Changed condition with the fields of former versions, patient ID and diagnosis date from the
+/// database, followed by the values of both sources
+#[derive(Serialize)]
+pub struct DiffItem<'a> {
+    pub pat_id: Option<&'a str>,
+    pub condition_id: &'a str,
+    pub diagnosis_date: &'a str,
+    pub csv_icd10_code: &'a str,
+    pub db_icd10_code: &'a str,
+    pub csv_diagnosis_date: &'a str,
+    pub db_diagnosis_date: &'a str,
+    pub csv_pat_id: Option<&'a str>,
+    pub db_pat_id: Option<&'a str>,
+    pub differences: &'a [Difference],
+}
+
+impl<'a> From<&'a DiffRecord> for DiffItem<'a> {
+    fn from(item: &'a DiffRecord) -> Self {
+        DiffItem {
+            pat_id: item.pat_id(),
+            condition_id: &item.condition_id,
+            diagnosis_date: &item.db_diagnosis_date,
+            csv_icd10_code: &item.csv_icd10_code,
+            db_icd10_code: &item.db_icd10_code,
+            csv_diagnosis_date: &item.csv_diagnosis_date,
+            db_diagnosis_date: &item.db_diagnosis_date,
+            csv_pat_id: item.csv_pat_id.as_deref(),
+            db_pat_id: item.db_pat_id.as_deref(),
+            differences: &item.differences,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CompareResult<'a> {
+    pub not_in_csv: Vec<CompareItem<'a>>,
+    pub not_in_db: Vec<CompareItem<'a>>,
+    pub icd10_diff: Vec<DiffItem<'a>>,
+    pub same_group_only: usize,
+    pub suppressed: usize,
+}
+
+impl<'a> CompareResult<'a> {
+    pub fn new(condition_diffs: &'a ConditionDiffs<'a>, icd_groups: &IcdGroups) -> Self {
+        CompareResult {
+            not_in_csv: condition_diffs
+                .not_in_csv
+                .iter()
+                .map(|&item| CompareItem::new(item, icd_groups))
+                .collect(),
+            not_in_db: condition_diffs
+                .not_in_db
+                .iter()
+                .map(|&item| CompareItem::new(item, icd_groups))
+                .collect(),
+            icd10_diff: condition_diffs.changed.iter().map(DiffItem::from).collect(),
+            same_group_only: condition_diffs.same_group_only(),
+            suppressed: condition_diffs.suppressed,
+        }
+    }
+}
+
+/// Row of `not_in_csv.csv` and `not_in_db.csv` written by `compare --output-dir`
+#[derive(Serialize)]
+pub struct CompareCsvRecord {
+    pub pat_id: Option<String>,
+    pub cond_id: String,
+    pub conditiondate: String,
+    pub condcodingcode: String,
+    pub icd10_group: String,
+    pub is_relevant: bool,
+}
+
+impl CompareCsvRecord {
+    pub fn new(item: &ExportData, icd_groups: &IcdGroups) -> Self {
+        CompareCsvRecord {
+            pat_id: item.pat_id.clone(),
+            cond_id: item.condition_id.to_string(),
+            conditiondate: item.diagnosis_date.to_string(),
+            condcodingcode: item.icd_10_code.to_string(),
+            icd10_group: Check::map_icd_code(&item.icd_10_code, icd_groups),
+            is_relevant: Check::is_relevant(&item.icd_10_code, icd_groups),
+        }
+    }
+}
+
+/// Row of `icd10_diff.csv` written by `compare --output-dir`. The columns of former versions are
+/// followed by the kinds of difference and the values of both sources.
+#[derive(Serialize)]
+pub struct DiffCsvRecord {
+    pub pat_id: Option<String>,
+    pub cond_id: String,
+    pub conditiondate: String,
+    pub csv_icd10_code: String,
+    pub csv_icd10_group: String,
+    pub csv_is_relevant: bool,
+    pub db_icd10_code: String,
+    pub db_icd10_group: String,
+    pub db_is_relevant: bool,
+    pub differences: String,
+    pub csv_pat_id: Option<String>,
+    pub db_pat_id: Option<String>,
+    pub csv_conditiondate: String,
+    pub db_conditiondate: String,
+}
+
+impl DiffCsvRecord {
+    pub fn new(item: &DiffRecord, icd_groups: &IcdGroups) -> Self {
+        DiffCsvRecord {
+            pat_id: item.pat_id().map(|pat_id| pat_id.to_string()),
+            cond_id: item.condition_id.to_string(),
+            conditiondate: item.db_diagnosis_date.to_string(),
+            csv_icd10_code: item.csv_icd10_code.to_string(),
+            csv_icd10_group: Check::map_icd_code(&item.csv_icd10_code, icd_groups),
+            csv_is_relevant: Check::is_relevant(&item.csv_icd10_code, icd_groups),
+            db_icd10_code: item.db_icd10_code.to_string(),
+            db_icd10_group: Check::map_icd_code(&item.db_icd10_code, icd_groups),
+            db_is_relevant: Check::is_relevant(&item.db_icd10_code, icd_groups),
+            differences: item.differences.iter().map(Difference::name).join(", "),
+            csv_pat_id: item.csv_pat_id.clone(),
+            db_pat_id: item.db_pat_id.clone(),
+            csv_conditiondate: item.csv_diagnosis_date.to_string(),
+            db_conditiondate: item.db_diagnosis_date.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ReportResult<'a> {
+    pub groups: CompareCountsResult<'a>,
+    pub conditions: CompareResult<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_export: Option<CheckExportResult>,
+}
+
+#[derive(Serialize)]
+pub struct MeldungReference {
+    pub meldung_id: String,
+    pub database_id: Option<String>,
+}
+
+impl From<&String> for MeldungReference {
+    fn from(id: &String) -> Self {
+        MeldungReference {
+            meldung_id: id.to_string(),
+            database_id: to_database_id(id),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CheckExportResult {
+    pub db_entries: usize,
+    pub db_meldungen: usize,
+    pub xml_meldungen: usize,
+    pub missing_in_db: Vec<MeldungReference>,
+    pub missing_in_xml: Vec<MeldungReference>,
+    pub multiple_meldungen: Vec<String>,
+    pub different_content: Vec<MeldungReference>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_changes: Option<Vec<ContentChanges>>,
+}
+
+impl CheckExportResult {
+    /// Adds the changes of all Meldungen with different content
+    pub fn with_changes(mut self, export_check: &ExportCheck) -> Self {
+        self.content_changes = Some(
+            export_check
+                .content_diffs
+                .iter()
+                .map(ContentChanges::from)
+                .collect(),
+        );
+        self
+    }
+}
+
+/// Changes from the protocol file to the database of a Meldung or the reason why they could
+/// not be determined
+#[derive(Serialize)]
+pub struct ContentChanges {
+    pub meldung_id: String,
+    pub database_id: Option<String>,
+    pub changes: Vec<XmlChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<&ContentDiff> for ContentChanges {
+    fn from(diff: &ContentDiff) -> Self {
+        let (changes, error) = match diff.changes() {
+            Ok(changes) => (changes, None),
+            Err(err) => (vec![], Some(err)),
+        };
+        ContentChanges {
+            meldung_id: diff.meldung_id.to_string(),
+            database_id: to_database_id(&diff.meldung_id),
+            changes,
+            error,
+        }
+    }
+}
+
+impl From<&ExportCheck> for CheckExportResult {
+    fn from(export_check: &ExportCheck) -> Self {
+        CheckExportResult {
+            db_entries: export_check.db_entries,
+            db_meldungen: export_check.db_meldungen,
+            xml_meldungen: export_check.xml_meldungen,
+            missing_in_db: export_check
+                .missing_in_db
+                .iter()
+                .map(MeldungReference::from)
+                .collect(),
+            missing_in_xml: export_check
+                .missing_in_xml
+                .iter()
+                .map(MeldungReference::from)
+                .collect(),
+            multiple_meldungen: export_check.multiple_meldungen.clone(),
+            different_content: export_check
+                .different_content
+                .iter()
+                .map(MeldungReference::from)
+                .collect(),
+            content_changes: None,
+        }
+    }
+}
@@ -0,0 +1,335 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+use crate::common::{Check, ConditionDiffs, Difference, ExportData, Icd10GroupSizeDiff};
+use crate::icd_groups::IcdGroups;
+use crate::lkrexport::{to_database_id, ExportCheck};
+use crate::output::Parameters;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; font-size: 0.9em; margin: 2em; }
+h1 { font-size: 1.6em; }
+h2 { font-size: 1.3em; margin-top: 2em; border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; margin: 1em 0; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }
+td.number { text-align: right; }
+tr.exceeds td, span.relevant { color: #b00; font-weight: bold; }
+code { font-size: 1.1em; }
+"#;
+
+/// Static HTML report of a complete plausibility check
+pub struct Report<'a> {
+    pub parameters: &'a Parameters,
+    pub created_at: String,
+    pub tolerance: f64,
+    pub group_sizes: &'a [Icd10GroupSizeDiff],
+    pub condition_diffs: &'a ConditionDiffs<'a>,
+    pub export_check: Option<&'a ExportCheck>,
+    pub icd_groups: &'a IcdGroups,
+}
+
+impl Report<'_> {
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+
+        html.push_str("<!DOCTYPE html>\n<html lang=\"de\">\n<head>\n<meta charset=\"UTF-8\">\n");
+        html.push_str("<title>BZKF RWDP Plausibilitätsprüfung</title>\n");
+        html.push_str(&format!("<style>{}</style>\n</head>\n<body>\n", STYLE));
+        html.push_str("<h1>BZKF Real World Data Platform - Plausibilitätsprüfung</h1>\n");
+
+        html.push_str(&self.run_html());
+        html.push_str(&self.group_sizes_html());
+        html.push_str(&self.condition_diffs_html());
+        if let Some(export_check) = self.export_check {
+            html.push_str(&Self::export_check_html(export_check));
+        }
+
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    fn run_html(&self) -> String {
+        let mut html = String::from("<h2>Durchführung</h2>\n<table>\n");
+        html.push_str(&format!(
+            "<tr><th>Version</th><td>{} {}</td></tr>\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ));
+        html.push_str(&format!(
+            "<tr><th>Erstellt am</th><td>{}</td></tr>\n",
+            escape(&self.created_at)
+        ));
+        html.push_str(&format!(
+            "<tr><th>Version der ICD-10-Gruppen</th><td>{}</td></tr>\n",
+            escape(&self.icd_groups.version)
+        ));
+
+        if let Ok(serde_json::Value::Object(parameters)) = serde_json::to_value(self.parameters) {
+            parameters.iter().for_each(|(name, value)| {
+                let value = match value {
+                    serde_json::Value::String(value) => value.to_string(),
+                    value => value.to_string(),
+                };
+                html.push_str(&format!(
+                    "<tr><th><code>{}</code></th><td>{}</td></tr>\n",
+                    escape(name),
+                    escape(&value)
+                ));
+            });
+        }
+
+        html.push_str("</table>\n");
+        html
+    }
+
+    fn group_sizes_html(&self) -> String {
+        let mut html = String::from("<h2>Anzahl der Conditions nach ICD-10-Gruppe</h2>\n");
+        html.push_str(&format!(
+            "<p>{} von {} ICD-10-Gruppen mit Abweichung über {}%</p>\n",
+            self.group_sizes
+                .iter()
+                .filter(|item| item.exceeds(self.tolerance))
+                .count(),
+            self.group_sizes.len(),
+            self.tolerance
+        ));
+        html.push_str("<table>\n<tr><th>ICD-10-Gruppe</th><th>CSV</th><th>DB</th><th>Diff</th><th>Diff (%)</th></tr>\n");
+
+        self.group_sizes.iter().for_each(|item| {
+            html.push_str(&format!(
+                "<tr{}><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{:+}</td><td class=\"number\">{}</td></tr>\n",
+                if item.exceeds(self.tolerance) { " class=\"exceeds\"" } else { "" },
+                escape(&item.name),
+                item.csv_size,
+                item.db_size,
+                item.delta(),
+                match item.relative_delta() {
+                    Some(relative_delta) => format!("{:+.2}", relative_delta),
+                    None => "-".to_string(),
+                }
+            ));
+        });
+
+        html.push_str(&format!(
+            "<tr><th>Gesamtsumme</th><th class=\"number\">{}</th><th class=\"number\">{}</th><th></th><th></th></tr>\n",
+            self.group_sizes.iter().map(|item| item.csv_size).sum::<usize>(),
+            self.group_sizes.iter().map(|item| item.db_size).sum::<usize>()
+        ));
+
+        html.push_str("</table>\n");
+        html
+    }
+
+    fn condition_diffs_html(&self) -> String {
+        fn conditions_html(items: &[&ExportData], icd_groups: &IcdGroups) -> String {
+            let mut html = String::from(
+                "<table>\n<tr><th>Condition-ID</th><th>Datum</th><th>ICD10</th><th>PAT-ID</th></tr>\n",
+            );
+            items.iter().for_each(|item| {
+                html.push_str(&format!(
+                    "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    escape(&item.condition_id),
+                    escape(&item.diagnosis_date),
+                    code_html(&item.icd_10_code, icd_groups),
+                    escape(item.pat_id.as_deref().unwrap_or_default())
+                ));
+            });
+            html.push_str("</table>\n");
+            html
+        }
+
+        let mut html = String::from("<h2>Abgleich der Conditions</h2>\n");
+
+        html.push_str(&format!(
+            "<h3>{} Conditions aus der Datenbank - aber nicht in der CSV-Datei</h3>\n",
+            self.condition_diffs.not_in_csv.len()
+        ));
+        html.push_str(&conditions_html(
+            &self.condition_diffs.not_in_csv,
+            self.icd_groups,
+        ));
+
+        html.push_str(&format!(
+            "<h3>{} Conditions aus der CSV-Datei - aber nicht in der Datenbank</h3>\n",
+            self.condition_diffs.not_in_db.len()
+        ));
+        html.push_str(&conditions_html(
+            &self.condition_diffs.not_in_db,
+            self.icd_groups,
+        ));
+
+        html.push_str(&format!(
+            "<h3>{} Conditions mit abweichenden Angaben, davon {} nur mit ICD10-Code in gleicher ICD-10-Gruppe</h3>\n",
+            self.condition_diffs.changed.len(),
+            self.condition_diffs.same_group_only()
+        ));
+        html.push_str("<table>\n<tr><th>Condition-ID</th><th>Abweichung</th><th>CSV</th><th>DB</th><th>PAT-ID</th></tr>\n");
+        self.condition_diffs.changed.iter().for_each(|item| {
+            let value_html = |difference: Difference, value: &str| match difference {
+                Difference::Icd10Code | Difference::Icd10Group => code_html(value, self.icd_groups),
+                _ => escape(value),
+            };
+            item.changed_values()
+                .into_iter()
+                .for_each(|(difference, csv_value, db_value)| {
+                    html.push_str(&format!(
+                        "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                        escape(&item.condition_id),
+                        difference,
+                        value_html(difference, csv_value),
+                        value_html(difference, db_value),
+                        escape(item.pat_id().unwrap_or_default())
+                    ));
+                });
+        });
+        html.push_str("</table>\n");
+
+        if self.condition_diffs.suppressed > 0 {
+            html.push_str(&format!(
+                "<p>{} bekannte Abweichungen laut Baseline ausgeblendet</p>\n",
+                self.condition_diffs.suppressed
+            ));
+        }
+
+        html
+    }
+
+    fn export_check_html(export_check: &ExportCheck) -> String {
+        fn ids_html(title: &str, ids: &[String]) -> String {
+            let mut html = format!("<h3>{} {}</h3>\n", ids.len(), title);
+            if !ids.is_empty() {
+                html.push_str("<table>\n<tr><th>Meldungs-ID</th><th>Datenbank-ID</th></tr>\n");
+                ids.iter().for_each(|id| {
+                    html.push_str(&format!(
+                        "<tr><td><code>{}</code></td><td>{}</td></tr>\n",
+                        escape(id),
+                        escape(&to_database_id(id).unwrap_or("?".into()))
+                    ));
+                });
+                html.push_str("</table>\n");
+            }
+            html
+        }
+
+        let mut html = String::from("<h2>Abgleich mit LKR-Export-Protokoll</h2>\n");
+        html.push_str(&format!(
+            "<p>{} Datenbankeinträge mit {} Meldungen, {} Meldungen in der Protokolldatei</p>\n",
+            export_check.db_entries, export_check.db_meldungen, export_check.xml_meldungen
+        ));
+
+        html.push_str(&ids_html(
+            "in der Datenbank fehlende Meldungen",
+            &export_check.missing_in_db,
+        ));
+        html.push_str(&ids_html(
+            "in der Protokolldatei fehlende Meldungen",
+            &export_check.missing_in_xml,
+        ));
+        html.push_str(&ids_html(
+            "Meldungen mit Unterschied zwischen Datenbank und Protokolldatei",
+            &export_check.different_content,
+        ));
+
+        html.push_str(&format!(
+            "<h3>{} Einträge in <code>lkr_meldung_export</code> mit mehreren Meldungsinhalten</h3>\n",
+            export_check.multiple_meldungen.len()
+        ));
+        if !export_check.multiple_meldungen.is_empty() {
+            html.push_str(&format!(
+                "<p>{}</p>\n",
+                escape(&export_check.multiple_meldungen.join(", "))
+            ));
+        }
+
+        html
+    }
+}
+
+fn code_html(code: &str, icd_groups: &IcdGroups) -> String {
+    match Check::is_relevant(code, icd_groups) {
+        true => format!("<span class=\"relevant\">{}</span>", escape(code)),
+        false => escape(code),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{ConditionDiffs, ExportData, Icd10GroupSizeDiff};
+    use crate::icd_groups::IcdGroups;
+    use crate::output::Parameters;
+    use crate::report::{escape, Report};
+
+    #[test]
+    fn should_escape_html() {
+        assert_eq!(
+            escape("<a href=\"#\">C50 & D05</a>"),
+            "&lt;a href=&quot;#&quot;&gt;C50 &amp; D05&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn should_render_report() {
+        let item = ExportData {
+            pat_id: None,
+            condition_id: "abc123".into(),
+            diagnosis_date: "01.01.2024".into(),
+            icd_10_code: "C61".into(),
+        };
+        let report = Report {
+            parameters: &Parameters {
+                year: Some("2024".into()),
+                ..Default::default()
+            },
+            created_at: "2024-06-01 12:00:00".into(),
+            tolerance: 0.0,
+            group_sizes: &[Icd10GroupSizeDiff {
+                name: "C50, D05".into(),
+                csv_size: 1,
+                db_size: 2,
+            }],
+            condition_diffs: &ConditionDiffs {
+                not_in_csv: vec![&item],
+                not_in_db: vec![],
+                changed: vec![],
+                suppressed: 2,
+            },
+            export_check: None,
+            icd_groups: &IcdGroups::default(),
+        };
+
+        let actual = report.to_html();
+
+        assert!(actual.contains("<tr><th><code>year</code></th><td>2024</td></tr>"));
+        assert!(actual.contains("<tr><th>Version der ICD-10-Gruppen</th><td>2024</td></tr>"));
+        assert!(actual.contains("<tr class=\"exceeds\"><td>C50, D05</td>"));
+        assert!(actual.contains("<span class=\"relevant\">C61</span>"));
+        assert!(actual.contains("2 bekannte Abweichungen laut Baseline ausgeblendet"));
+        assert!(!actual.contains("Abgleich mit LKR-Export-Protokoll"));
+    }
+}
@@ -0,0 +1,32 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+SELECT
+    lme.id,
+    lme.lkr_meldung,
+    lme.lkr_export,
+    lme.typ,
+    lme.versionsnummer,
+    lme.xml_daten,
+    DATE_FORMAT(le.exportiert_am, '%Y-%m-%d %H:%i:%s') AS exportiert_am
+FROM lkr_meldung_export lme
+    LEFT OUTER JOIN lkr_export le ON (lme.lkr_export = le.id)
+WHERE lme.xml_daten LIKE CONCAT('%', :containing, '%')
+ORDER BY lme.id;
\ No newline at end of file
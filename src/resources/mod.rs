@@ -18,10 +18,8 @@
  * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
  */
 
-pub const SQL_QUERY: &str = include_str!("query.sql");
-
-pub const SQL_QUERY_WITH_SCHEMA_VERSION: &str = include_str!("query_with_schema_version.sql");
+pub const EXPORTED_TO_LKR: &str = include_str!("exported-to-lkr.sql");
 
-pub const EXPORT_QUERY: &str = include_str!("export.sql");
+pub const MELDUNG_EXPORT: &str = include_str!("meldung-export.sql");
 
-pub const EXPORTED_TO_LKR: &str = include_str!("exported-to-lkr.sql");
+pub const ICD_GROUPS: &str = include_str!("icd-groups.toml");
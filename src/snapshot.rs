@@ -0,0 +1,312 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+//! Local copy of `lkr_meldung_export` stored as gzip compressed JSON lines.
+//! The first line contains the header, each following line one row.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::common::MeldungExport;
+use crate::filter::{EntryRules, MeldungEntry, PatientFilter};
+
+const FORMAT_VERSION: u8 = 1;
+
+/// First line of a snapshot file
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    pub format_version: u8,
+    pub created_at: String,
+    pub database: String,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Snapshot file could not be read or written
+    Io(PathBuf, std::io::Error),
+    /// Line of the snapshot file is not valid
+    InvalidContent(PathBuf, usize, serde_json::Error),
+    /// Snapshot file was written using an unknown format
+    UnsupportedFormat(PathBuf, u8),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(path, err) if err.kind() == ErrorKind::NotFound => write!(
+                f,
+                "Snapshot-Datei '{}' nicht gefunden",
+                path.to_str().unwrap_or_default()
+            ),
+            SnapshotError::Io(path, err) => write!(
+                f,
+                "Fehler beim Zugriff auf Snapshot-Datei '{}': {}",
+                path.to_str().unwrap_or_default(),
+                err
+            ),
+            SnapshotError::InvalidContent(path, line, err) => write!(
+                f,
+                "Ungültiger Inhalt in Snapshot-Datei '{}' in Zeile {}: {}",
+                path.to_str().unwrap_or_default(),
+                line,
+                err
+            ),
+            SnapshotError::UnsupportedFormat(path, version) => write!(
+                f,
+                "Snapshot-Datei '{}' hat nicht unterstütztes Format {}",
+                path.to_str().unwrap_or_default(),
+                version
+            ),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SnapshotError::Io(_, err) => Some(err),
+            SnapshotError::InvalidContent(_, _, err) => Some(err),
+            SnapshotError::UnsupportedFormat(_, _) => None,
+        }
+    }
+}
+
+/// Writes rows into a new snapshot file
+pub struct SnapshotWriter {
+    path: PathBuf,
+    encoder: GzEncoder<BufWriter<File>>,
+    rows: usize,
+}
+
+impl SnapshotWriter {
+    pub fn create(path: &Path, database: &str, created_at: &str) -> Result<Self, SnapshotError> {
+        let file = File::create(path).map_err(|err| SnapshotError::Io(path.to_path_buf(), err))?;
+        let mut writer = SnapshotWriter {
+            path: path.to_path_buf(),
+            encoder: GzEncoder::new(BufWriter::new(file), Compression::default()),
+            rows: 0,
+        };
+
+        writer.write_line(&SnapshotHeader {
+            format_version: FORMAT_VERSION,
+            created_at: created_at.to_string(),
+            database: database.to_string(),
+        })?;
+
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, row: &MeldungExport) -> Result<(), SnapshotError> {
+        self.write_line(row)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Completes the compressed file and returns the number of written rows
+    pub fn finish(self) -> Result<usize, SnapshotError> {
+        self.encoder
+            .finish()
+            .and_then(|mut writer| writer.flush())
+            .map_err(|err| SnapshotError::Io(self.path.to_path_buf(), err))?;
+        Ok(self.rows)
+    }
+
+    fn write_line<T: Serialize>(&mut self, value: &T) -> Result<(), SnapshotError> {
+        serde_json::to_writer(&mut self.encoder, value)
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.encoder.write_all(b"\n"))
+            .map_err(|err| SnapshotError::Io(self.path.to_path_buf(), err))
+    }
+}
+
+/// Snapshot file used instead of a database connection
+pub struct Snapshot {
+    path: PathBuf,
+    pub header: SnapshotHeader,
+}
+
+impl Snapshot {
+    pub fn open(path: &Path) -> Result<Self, SnapshotError> {
+        let mut lines = Self::lines(path)?;
+
+        let header = match lines.next() {
+            Some((_, line)) => serde_json::from_str::<SnapshotHeader>(&line?)
+                .map_err(|err| SnapshotError::InvalidContent(path.to_path_buf(), 1, err))?,
+            None => {
+                return Err(SnapshotError::Io(
+                    path.to_path_buf(),
+                    ErrorKind::UnexpectedEof.into(),
+                ))
+            }
+        };
+
+        if header.format_version != FORMAT_VERSION {
+            return Err(SnapshotError::UnsupportedFormat(
+                path.to_path_buf(),
+                header.format_version,
+            ));
+        }
+
+        Ok(Snapshot {
+            path: path.to_path_buf(),
+            header,
+        })
+    }
+
+    /// Entries of the given LKR export like `exported-to-lkr.sql`.
+    /// Package `0` selects the latest export.
+    pub fn exported(&self, package: u16) -> Result<Vec<(String, String)>, SnapshotError> {
+        let mut selected_package = match package {
+            0 => None,
+            package => Some(package as u64),
+        };
+        let mut result = vec![];
+
+        for row in self.rows()? {
+            let row = row?;
+            if package == 0 && row.lkr_export > selected_package {
+                selected_package = row.lkr_export;
+                result.clear();
+            }
+            if row.typ.is_some_and(|typ| typ != -1)
+                && row.lkr_export.is_some()
+                && row.lkr_export == selected_package
+            {
+                result.push((row.id.to_string(), row.xml_daten));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Entries of `lkr_meldung_export` with given diagnosis year
+    pub fn entries(
+        &self,
+        year: &str,
+        rules: &EntryRules,
+    ) -> Result<Vec<MeldungEntry>, SnapshotError> {
+        let mut entries = vec![];
+        for row in self.rows()? {
+            let entry = MeldungEntry::new(&row?, rules);
+            if entry.diagnosis_year == year {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Entries of `lkr_meldung_export` of given condition and patient in any diagnosis year
+    pub fn condition_entries(
+        &self,
+        condition_id: &str,
+        patient_id: &str,
+        rules: &EntryRules,
+    ) -> Result<Vec<MeldungEntry>, SnapshotError> {
+        let patient_filter = PatientFilter::new(patient_id);
+        let mut entries = vec![];
+        for row in self.rows()? {
+            let row = row?;
+            if !patient_filter.is_match(&row.xml_daten) {
+                continue;
+            }
+            let entry = MeldungEntry::new(&row, rules);
+            if entry.condition_id == condition_id && entry.patient_id == patient_id {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    fn rows(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<MeldungExport, SnapshotError>> + '_, SnapshotError>
+    {
+        Ok(Self::lines(&self.path)?.skip(1).map(|(index, line)| {
+            serde_json::from_str::<MeldungExport>(&line?).map_err(|err| {
+                SnapshotError::InvalidContent(self.path.to_path_buf(), index + 1, err)
+            })
+        }))
+    }
+
+    fn lines(
+        path: &Path,
+    ) -> Result<impl Iterator<Item = (usize, Result<String, SnapshotError>)> + '_, SnapshotError>
+    {
+        let file = File::open(path).map_err(|err| SnapshotError::Io(path.to_path_buf(), err))?;
+        Ok(BufReader::new(GzDecoder::new(file))
+            .lines()
+            .map(|line| line.map_err(|err| SnapshotError::Io(path.to_path_buf(), err)))
+            .enumerate())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use crate::common::MeldungExport;
+    use crate::snapshot::{Snapshot, SnapshotWriter};
+
+    fn row(id: u64, lkr_export: u64, typ: i64) -> MeldungExport {
+        MeldungExport {
+            id,
+            lkr_meldung: Some(id),
+            lkr_export: Some(lkr_export),
+            typ: Some(typ),
+            versionsnummer: Some(1),
+            xml_daten: format!(
+                "<ADT_GEKID><Meldung Meldung_ID=\"TEST{}\"/></ADT_GEKID>",
+                id
+            ),
+            exportiert_am: None,
+        }
+    }
+
+    #[test]
+    fn should_write_and_read_snapshot() {
+        let path = env::temp_dir().join(format!("bzkf-rwdp-check-{}.jsonl.gz", std::process::id()));
+
+        let mut writer = SnapshotWriter::create(&path, "onkostar", "2024-06-01 12:00:00").unwrap();
+        writer.write(&row(1, 1, 0)).unwrap();
+        writer.write(&row(2, 2, 0)).unwrap();
+        writer.write(&row(3, 2, -1)).unwrap();
+        assert_eq!(writer.finish().unwrap(), 3);
+
+        let snapshot = Snapshot::open(&path).unwrap();
+        let latest = snapshot.exported(0).unwrap();
+        let first = snapshot.exported(1).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(snapshot.header.database, "onkostar");
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].0, "2");
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].0, "1");
+    }
+}
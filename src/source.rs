@@ -0,0 +1,275 @@
+/*
+ * This file is part of bzkf-rwdp-check
+ *
+ * Copyright (C) 2024 the original author or authors.
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::common::{ExportData, Icd10GroupSize};
+use crate::database::{DatabaseError, DatabaseSource};
+use crate::filter;
+use crate::filter::{
+    DiagnosisPeriod, EntryRules, Exclusion, ExclusionReason, MeldendeStelleSize, MeldungEntry,
+};
+use crate::icd_groups::IcdGroups;
+use crate::snapshot::{Snapshot, SnapshotError};
+
+/// Source of `lkr_meldung_export` entries: a live database connection or a snapshot file.
+/// Entries of a diagnosis year are fetched only once and reused by all checks of a command.
+pub struct Source {
+    kind: SourceKind,
+    rules: EntryRules,
+    entries: HashMap<String, Vec<MeldungEntry>>,
+}
+
+enum SourceKind {
+    Database(DatabaseSource),
+    Snapshot(Snapshot),
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    Database(DatabaseError),
+    Snapshot(SnapshotError),
+}
+
+impl Display for SourceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceError::Database(err) => err.fmt(f),
+            SourceError::Snapshot(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for SourceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SourceError::Database(err) => err.source(),
+            SourceError::Snapshot(err) => err.source(),
+        }
+    }
+}
+
+impl From<DatabaseError> for SourceError {
+    fn from(err: DatabaseError) -> Self {
+        SourceError::Database(err)
+    }
+}
+
+impl From<SnapshotError> for SourceError {
+    fn from(err: SnapshotError) -> Self {
+        SourceError::Snapshot(err)
+    }
+}
+
+impl Source {
+    pub fn database(db: DatabaseSource, rules: EntryRules) -> Self {
+        Source {
+            kind: SourceKind::Database(db),
+            rules,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn snapshot(snapshot: Snapshot, rules: EntryRules) -> Self {
+        Source {
+            kind: SourceKind::Snapshot(snapshot),
+            rules,
+            entries: HashMap::new(),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn check(
+        &mut self,
+        period: &DiagnosisPeriod,
+        ignore_exports_since: &str,
+        ignore_non_obds_2: bool,
+        include_extern: bool,
+        include_histo_zyto: bool,
+        schema_versions: bool,
+        icd_groups: &IcdGroups,
+    ) -> Result<Vec<Icd10GroupSize>, SourceError> {
+        Ok(filter::group_sizes(
+            &self.entries(period)?,
+            period,
+            ignore_exports_since,
+            ignore_non_obds_2,
+            include_extern,
+            include_histo_zyto,
+            schema_versions,
+            icd_groups,
+        ))
+    }
+
+    pub fn export(
+        &mut self,
+        period: &DiagnosisPeriod,
+        ignore_exports_since: &str,
+        ignore_non_obds_2: bool,
+        use_pat_id: bool,
+        include_extern: bool,
+        include_histo_zyto: bool,
+    ) -> Result<Vec<ExportData>, SourceError> {
+        Ok(filter::conditions(
+            &self.entries(period)?,
+            period,
+            ignore_exports_since,
+            ignore_non_obds_2,
+            use_pat_id,
+            include_extern,
+            include_histo_zyto,
+        ))
+    }
+
+    /// Conditions counted by `check()` by `Meldende_Stelle`
+    pub fn meldende_stellen(
+        &mut self,
+        period: &DiagnosisPeriod,
+        ignore_exports_since: &str,
+        ignore_non_obds_2: bool,
+        include_extern: bool,
+        include_histo_zyto: bool,
+    ) -> Result<Vec<MeldendeStelleSize>, SourceError> {
+        Ok(filter::meldende_stellen(
+            &self.entries(period)?,
+            period,
+            ignore_exports_since,
+            ignore_non_obds_2,
+            include_extern,
+            include_histo_zyto,
+        ))
+    }
+
+    /// Entries within the given period not counted by `check()` and the rules excluding them
+    pub fn excluded(
+        &mut self,
+        period: &DiagnosisPeriod,
+        ignore_exports_since: &str,
+        ignore_non_obds_2: bool,
+        include_extern: bool,
+        include_histo_zyto: bool,
+    ) -> Result<Vec<Exclusion>, SourceError> {
+        Ok(filter::group_size_exclusions(
+            &self.entries(period)?,
+            Some(period),
+            ignore_exports_since,
+            ignore_non_obds_2,
+            include_extern,
+            include_histo_zyto,
+        )
+        .into_iter()
+        .filter(|exclusion| {
+            !exclusion.is_selected()
+                && !exclusion
+                    .reasons
+                    .contains(&ExclusionReason::DiagnosisPeriod)
+        })
+        .collect())
+    }
+
+    /// All entries of the given condition and the rules of `export()` excluding them.
+    /// Without diagnosis period all entries of the given patient are read.
+    #[allow(clippy::too_many_arguments)]
+    pub fn explain(
+        &mut self,
+        condition_id: &str,
+        period: Option<&DiagnosisPeriod>,
+        patient_id: Option<&str>,
+        ignore_exports_since: &str,
+        ignore_non_obds_2: bool,
+        include_extern: bool,
+        include_histo_zyto: bool,
+    ) -> Result<Vec<Exclusion>, SourceError> {
+        let condition_entries;
+        let entries = match period {
+            Some(period) => self
+                .entries(period)?
+                .into_iter()
+                .filter(|entry| entry.condition_id == condition_id)
+                .collect(),
+            None => {
+                let patient_id = patient_id.unwrap_or_default();
+                condition_entries = match self.kind {
+                    SourceKind::Database(ref mut db) => {
+                        db.condition_entries(condition_id, patient_id, &self.rules)?
+                    }
+                    SourceKind::Snapshot(ref snapshot) => {
+                        snapshot.condition_entries(condition_id, patient_id, &self.rules)?
+                    }
+                };
+                condition_entries.iter().collect::<Vec<_>>()
+            }
+        };
+
+        Ok(filter::condition_exclusions(
+            &entries,
+            period,
+            ignore_exports_since,
+            ignore_non_obds_2,
+            include_extern,
+            include_histo_zyto,
+        ))
+    }
+
+    /// Condition IDs of all entries within the given period by Meldung ID
+    pub fn meldung_conditions(
+        &mut self,
+        period: &DiagnosisPeriod,
+    ) -> Result<HashMap<String, String>, SourceError> {
+        Ok(self
+            .entries(period)?
+            .into_iter()
+            .filter(|entry| period.contains(entry))
+            .filter_map(|entry| {
+                entry
+                    .meldung_id
+                    .as_ref()
+                    .map(|meldung_id| (meldung_id.to_string(), entry.condition_id.to_string()))
+            })
+            .collect())
+    }
+
+    pub fn exported(&mut self, package: u16) -> Result<Vec<(String, String)>, SourceError> {
+        match self.kind {
+            SourceKind::Database(ref mut db) => Ok(db.exported(package)?),
+            SourceKind::Snapshot(ref snapshot) => Ok(snapshot.exported(package)?),
+        }
+    }
+
+    /// Entries of all diagnosis years of the given period
+    fn entries(&mut self, period: &DiagnosisPeriod) -> Result<Vec<&MeldungEntry>, SourceError> {
+        let years = period.years();
+        for year in &years {
+            if !self.entries.contains_key(year) {
+                let entries = match self.kind {
+                    SourceKind::Database(ref mut db) => db.entries(year, &self.rules)?,
+                    SourceKind::Snapshot(ref snapshot) => snapshot.entries(year, &self.rules)?,
+                };
+                self.entries.insert(year.to_string(), entries);
+            }
+        }
+        Ok(years
+            .iter()
+            .flat_map(|year| self.entries[year].iter())
+            .collect())
+    }
+}